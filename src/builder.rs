@@ -0,0 +1,339 @@
+// Copyright 2014 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Configuration of new pools.
+
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use super::hooks::Hooks;
+use super::index::WorkerIndices;
+use super::panics::PanicLog;
+#[cfg(feature = "prometheus")]
+use super::prometheus::JobDurations;
+use super::queue::{CustomQueue, JobQueue};
+use super::schedule::Scheduler;
+use super::steal::WorkerQueues;
+#[cfg(feature = "tracing")]
+use super::trace::Tracer;
+use super::watchdog::JobMonitor;
+use super::{spawn_in_pool, ConfigError, ThreadPool, ThreadPoolSharedData};
+
+/// A builder for a [`ThreadPool`] with a custom configuration.
+///
+/// Every setting is optional. By default the pool has as many threads as the
+/// machine has CPUs, all of them spawned up front, unnamed threads with the
+/// default stack size, and an unbounded queue. The configuration is checked
+/// when the pool is built, so a mistake is reported as a [`ConfigError`]
+/// instead of a panic.
+///
+/// # Example
+///
+/// ```rust
+/// use threadpool::ThreadPoolBuilder;
+/// use std::time::Duration;
+///
+/// let pool = ThreadPoolBuilder::new()
+///     .thread_name("ingest")
+///     .num_threads(8)
+///     .initial_threads(2)
+///     .thread_stack_size(4 * 1024 * 1024)
+///     .keep_alive(Duration::from_secs(30))
+///     .queue_capacity(1024)
+///     .build()
+///     .unwrap();
+///
+/// assert_eq!(pool.max_count(), 8);
+/// assert_eq!(pool.queue_capacity(), Some(1024));
+/// ```
+///
+/// [`ThreadPool`]: struct.ThreadPool.html
+/// [`ConfigError`]: enum.ConfigError.html
+#[derive(Clone, Debug, Default)]
+pub struct ThreadPoolBuilder {
+    name: Option<String>,
+    num_threads: Option<usize>,
+    initial_threads: Option<usize>,
+    stack_size: Option<usize>,
+    keep_alive: Option<Duration>,
+    queue_capacity: Option<usize>,
+    job_queue: CustomQueue,
+    pub(crate) hooks: Hooks,
+    #[cfg(feature = "tracing")]
+    pub(crate) job_spans: bool,
+}
+
+impl ThreadPoolBuilder {
+    /// Creates a builder with the default configuration.
+    pub fn new() -> ThreadPoolBuilder {
+        ThreadPoolBuilder::default()
+    }
+
+    /// Sets the [name][thread name] of the worker threads.
+    ///
+    /// The first `{}` in `name` is replaced by the
+    /// [index](struct.ThreadPool.html#method.current_worker_index) of each
+    /// worker, so `"ingest-{}"` names the workers `ingest-0`, `ingest-1` and so
+    /// on. Without a `{}`, all workers have the same name.
+    ///
+    /// [thread name]: https://doc.rust-lang.org/std/thread/struct.Thread.html#method.name
+    pub fn thread_name<S: Into<String>>(mut self, name: S) -> ThreadPoolBuilder {
+        self.name = Some(name.into());
+        self
+    }
+
+    /// Sets the maximum number of threads. Defaults to the number of CPUs.
+    pub fn num_threads(mut self, num_threads: usize) -> ThreadPoolBuilder {
+        self.num_threads = Some(num_threads);
+        self
+    }
+
+    /// Sets the number of threads spawned when the pool is built. Defaults to
+    /// the maximum number of threads.
+    ///
+    /// A pool with fewer initial threads than its maximum is dynamic: it
+    /// spawns more threads when it is busy and lets them exit again when it is
+    /// not, as described in [`ThreadPool::new_dynamic`].
+    ///
+    /// [`ThreadPool::new_dynamic`]: struct.ThreadPool.html#method.new_dynamic
+    pub fn initial_threads(mut self, initial_threads: usize) -> ThreadPoolBuilder {
+        self.initial_threads = Some(initial_threads);
+        self
+    }
+
+    /// Sets the stack size of the worker threads, in bytes. Defaults to the
+    /// stack size of [`std::thread`].
+    ///
+    /// [`std::thread`]: https://doc.rust-lang.org/std/thread/index.html#stack-size
+    pub fn thread_stack_size(mut self, stack_size: usize) -> ThreadPoolBuilder {
+        self.stack_size = Some(stack_size);
+        self
+    }
+
+    /// Sets how long a worker thread of a dynamic pool waits for a job before
+    /// it exits, as long as more than the initial number of threads are left.
+    /// By default idle threads wait indefinitely. See
+    /// [`ThreadPool::set_keep_alive`].
+    ///
+    /// [`ThreadPool::set_keep_alive`]: struct.ThreadPool.html#method.set_keep_alive
+    pub fn keep_alive(mut self, keep_alive: Duration) -> ThreadPoolBuilder {
+        self.keep_alive = Some(keep_alive);
+        self
+    }
+
+    /// Limits the number of jobs waiting in the queue. By default the queue is
+    /// unbounded. See [`ThreadPool::set_queue_capacity`].
+    ///
+    /// [`ThreadPool::set_queue_capacity`]: struct.ThreadPool.html#method.set_queue_capacity
+    pub fn queue_capacity(mut self, capacity: usize) -> ThreadPoolBuilder {
+        self.queue_capacity = Some(capacity);
+        self
+    }
+
+    /// Makes the jobs of the pool wait in `queue` until a worker takes them,
+    /// instead of in the default queue, which orders them by
+    /// [`Priority`]. See [`ThreadPool::with_job_queue`].
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use threadpool::{RingQueue, ThreadPoolBuilder};
+    ///
+    /// let pool = ThreadPoolBuilder::new()
+    ///     .thread_name("ring-{}")
+    ///     .num_threads(4)
+    ///     .job_queue(RingQueue::new(1024))
+    ///     .build()
+    ///     .unwrap();
+    /// pool.execute(|| println!("hello from a ring"));
+    /// pool.join();
+    /// ```
+    ///
+    /// [`Priority`]: enum.Priority.html
+    /// [`ThreadPool::with_job_queue`]: struct.ThreadPool.html#method.with_job_queue
+    pub fn job_queue<Q>(mut self, queue: Q) -> ThreadPoolBuilder
+        where Q: JobQueue + 'static
+    {
+        self.job_queue = CustomQueue::new(Arc::new(queue));
+        self
+    }
+
+    /// Checks the configuration and spawns the pool.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use threadpool::{ConfigError, ThreadPoolBuilder};
+    ///
+    /// match ThreadPoolBuilder::new().num_threads(2).initial_threads(4).build() {
+    ///     Err(ConfigError::InvalidInitialThreads) => {}
+    ///     _ => panic!("a pool cannot start with more threads than its maximum"),
+    /// }
+    /// ```
+    pub fn build(self) -> Result<ThreadPool, ConfigError> {
+        let num_threads = self.num_threads.unwrap_or_else(default_num_threads);
+        let initial_threads = self.initial_threads.unwrap_or(num_threads);
+        if num_threads == 0 {
+            return Err(ConfigError::ZeroThreads);
+        }
+        if initial_threads == 0 || initial_threads > num_threads {
+            return Err(ConfigError::InvalidInitialThreads);
+        }
+        if self.stack_size == Some(0) {
+            return Err(ConfigError::ZeroStackSize);
+        }
+        if self.keep_alive == Some(Duration::from_secs(0)) {
+            return Err(ConfigError::ZeroKeepAlive);
+        }
+        if self.queue_capacity == Some(0) {
+            return Err(ConfigError::ZeroQueueCapacity);
+        }
+        let job_queue = self.job_queue.into_injector();
+        if self.queue_capacity.is_some() {
+            job_queue.set_capacity(self.queue_capacity);
+        }
+
+        #[cfg(feature = "tracing")]
+        let tracer = Tracer::new(self.name.as_ref().map(|name| &name[..]), self.job_spans);
+        let shared_data = Arc::new(ThreadPoolSharedData {
+            name: self.name,
+            hooks: self.hooks,
+            stack_size: self.stack_size,
+            keep_alive_nanos: AtomicU64::new(self.keep_alive.map_or(0, duration_nanos)),
+            job_queue,
+            worker_indices: WorkerIndices::new(),
+            worker_queues: WorkerQueues::new(),
+            scheduler: Scheduler::new(),
+            job_monitor: JobMonitor::new(),
+            panic_log: PanicLog::new(),
+            handle_count: AtomicUsize::new(1),
+            empty_trigger: Mutex::new(()),
+            empty_condvar: Condvar::new(),
+            terminated_trigger: Mutex::new(()),
+            terminated_condvar: Condvar::new(),
+            join_generation: AtomicUsize::new(0),
+            queued_count: AtomicUsize::new(0),
+            active_count: AtomicUsize::new(0),
+            spawned_count: AtomicUsize::new(0),
+            min_count: AtomicUsize::new(initial_threads),
+            max_count: AtomicUsize::new(num_threads),
+            compensating_count: AtomicUsize::new(0),
+            panic_count: AtomicUsize::new(0),
+            joined_panic_count: AtomicUsize::new(0),
+            completed_count: AtomicUsize::new(0),
+            cancelled_count: AtomicUsize::new(0),
+            submitted_count: AtomicUsize::new(0),
+            threads_spawned: AtomicUsize::new(0),
+            threads_retired: AtomicUsize::new(0),
+            peak_queued: AtomicUsize::new(0),
+            peak_active: AtomicUsize::new(0),
+            #[cfg(feature = "prometheus")]
+            job_durations: JobDurations::new(),
+            #[cfg(feature = "tracing")]
+            tracer,
+        });
+
+        // Dropping the pool on error shuts down the threads spawned so far.
+        let pool = ThreadPool { shared_data };
+
+        // Threadpool threads
+        for _ in 0..initial_threads {
+            pool.shared_data.spawned_count.fetch_add(1, Ordering::SeqCst);
+            if let Err(err) = spawn_in_pool(pool.shared_data.clone()) {
+                pool.shared_data.spawned_count.fetch_sub(1, Ordering::SeqCst);
+                return Err(ConfigError::SpawnFailed(err));
+            }
+        }
+
+        Ok(pool)
+    }
+}
+
+fn default_num_threads() -> usize {
+    thread::available_parallelism().map_or(1, |n| n.get())
+}
+
+/// Converts `duration` to nanoseconds, saturating at `u64::MAX`.
+pub(crate) fn duration_nanos(duration: Duration) -> u64 {
+    duration.as_nanos().min(u64::MAX as u128) as u64
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::{ConfigError, ThreadPoolBuilder};
+    use std::sync::mpsc::channel;
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn test_builder_rejects_invalid_config() {
+        fn error(builder: ThreadPoolBuilder) -> ConfigError {
+            builder.build().err().unwrap()
+        }
+
+        match error(ThreadPoolBuilder::new().num_threads(0)) {
+            ConfigError::ZeroThreads => {}
+            err => panic!("unexpected error: {}", err),
+        }
+        match error(ThreadPoolBuilder::new().num_threads(2).initial_threads(0)) {
+            ConfigError::InvalidInitialThreads => {}
+            err => panic!("unexpected error: {}", err),
+        }
+        match error(ThreadPoolBuilder::new().thread_stack_size(0)) {
+            ConfigError::ZeroStackSize => {}
+            err => panic!("unexpected error: {}", err),
+        }
+        match error(ThreadPoolBuilder::new().keep_alive(Duration::from_secs(0))) {
+            ConfigError::ZeroKeepAlive => {}
+            err => panic!("unexpected error: {}", err),
+        }
+        match error(ThreadPoolBuilder::new().queue_capacity(0)) {
+            ConfigError::ZeroQueueCapacity => {}
+            err => panic!("unexpected error: {}", err),
+        }
+    }
+
+    #[test]
+    fn test_builder_configures_threads() {
+        let pool = ThreadPoolBuilder::new()
+            .thread_name("built")
+            .num_threads(3)
+            .thread_stack_size(256 * 1024)
+            .build()
+            .unwrap();
+        assert_eq!(pool.max_count(), 3);
+        assert_eq!(pool.spawned_count(), 3);
+
+        let (tx, rx) = channel();
+        pool.execute(move || {
+            // Use a good part of the stack to make sure its size was applied.
+            let buffer = [1u8; 128 * 1024];
+            let sum: u32 = buffer.iter().map(|&b| b as u32).sum();
+            tx.send((thread::current().name().map(|name| name.to_owned()), sum)).unwrap();
+        });
+        assert_eq!(rx.recv().unwrap(), (Some("built".to_owned()), 128 * 1024));
+    }
+
+    #[test]
+    fn test_builder_configures_keep_alive() {
+        let pool = ThreadPoolBuilder::new()
+            .num_threads(4)
+            .initial_threads(1)
+            .keep_alive(Duration::from_millis(50))
+            .build()
+            .unwrap();
+        assert_eq!(pool.keep_alive(), Some(Duration::from_millis(50)));
+        assert_eq!(pool.min_count(), 1);
+        assert_eq!(pool.max_count(), 4);
+        assert_eq!(pool.spawned_count(), 1);
+    }
+}
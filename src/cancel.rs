@@ -0,0 +1,49 @@
+// Copyright 2014 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Cooperative cancellation of jobs.
+
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// A shared flag used to cancel jobs.
+///
+/// A token is attached to a job when submitting it with a [`JobBuilder`]. If
+/// the token is cancelled before the job starts, the job is dropped without
+/// running when a worker takes it from the queue. A job that is already
+/// running is not interrupted, but it can check
+/// [`is_cancelled`](#method.is_cancelled) on a clone of the token and stop
+/// early.
+///
+/// Clones of a token share the same flag, so one token can cancel any number
+/// of jobs at once.
+///
+/// [`JobBuilder`]: struct.JobBuilder.html
+#[derive(Clone, Debug, Default)]
+pub struct CancellationToken {
+    cancelled: Arc<AtomicBool>,
+}
+
+impl CancellationToken {
+    /// Creates a new token that is not cancelled.
+    pub fn new() -> CancellationToken {
+        CancellationToken::default()
+    }
+
+    /// Cancels all jobs this token is attached to.
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::SeqCst);
+    }
+
+    /// Returns `true` if the token has been cancelled.
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::SeqCst)
+    }
+}
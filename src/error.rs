@@ -0,0 +1,96 @@
+// Copyright 2014 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Errors reported by the thread pool and its builder.
+
+use std::error::Error;
+use std::fmt;
+use std::io;
+
+/// An error returned when a pool could not be created or a job could not be
+/// submitted to it.
+#[derive(Debug)]
+pub enum PoolError {
+    /// The pool has been shut down and no longer accepts jobs.
+    ShutDown,
+    /// The queue of the pool is full and its rejection policy refuses new jobs.
+    QueueFull,
+    /// A worker thread could not be spawned and the pool has no other threads
+    /// to run the job on.
+    SpawnFailed(io::Error),
+    /// The number of threads is 0, or the number of initial threads is greater
+    /// than the maximum number of threads.
+    InvalidThreadCount,
+}
+
+impl fmt::Display for PoolError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            PoolError::ShutDown => write!(f, "thread pool has been shut down"),
+            PoolError::QueueFull => write!(f, "thread pool queue is full"),
+            PoolError::SpawnFailed(ref err) => write!(f, "failed to spawn worker thread: {}", err),
+            PoolError::InvalidThreadCount => write!(f, "invalid number of threads"),
+        }
+    }
+}
+
+impl Error for PoolError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match *self {
+            PoolError::SpawnFailed(ref err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+/// An error returned by [`ThreadPoolBuilder::build`] when the configuration is
+/// invalid or the pool could not be created.
+///
+/// [`ThreadPoolBuilder::build`]: struct.ThreadPoolBuilder.html#method.build
+#[derive(Debug)]
+pub enum ConfigError {
+    /// The maximum number of threads is 0.
+    ZeroThreads,
+    /// The number of initial threads is 0 or greater than the maximum number of
+    /// threads.
+    InvalidInitialThreads,
+    /// The stack size of the worker threads is 0.
+    ZeroStackSize,
+    /// The keep-alive duration of idle worker threads is zero.
+    ZeroKeepAlive,
+    /// The queue capacity is 0.
+    ZeroQueueCapacity,
+    /// A worker thread could not be spawned.
+    SpawnFailed(io::Error),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ConfigError::ZeroThreads => write!(f, "thread pool needs at least one thread"),
+            ConfigError::InvalidInitialThreads => {
+                write!(f, "number of initial threads must be between 1 and the maximum")
+            }
+            ConfigError::ZeroStackSize => write!(f, "thread stack size must not be 0"),
+            ConfigError::ZeroKeepAlive => write!(f, "keep-alive duration must not be zero"),
+            ConfigError::ZeroQueueCapacity => write!(f, "queue capacity must not be 0"),
+            ConfigError::SpawnFailed(ref err) => write!(f, "failed to spawn worker thread: {}", err),
+        }
+    }
+}
+
+impl Error for ConfigError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match *self {
+            ConfigError::SpawnFailed(ref err) => Some(err),
+            _ => None,
+        }
+    }
+}
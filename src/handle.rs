@@ -0,0 +1,198 @@
+// Copyright 2014 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Handles to the results of jobs submitted with `ThreadPool::spawn`.
+
+use std::any::Any;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError, TryRecvError};
+use std::thread;
+use std::time::Duration;
+
+use super::Thunk;
+
+/// The payload reported when a job was dropped before it could produce a result.
+const JOB_LOST: &str = "job was dropped before it completed";
+
+/// An owned permission to join on a job submitted with [`ThreadPool::spawn`].
+///
+/// If the job panicked, joining returns `Err` with the panic payload, just like
+/// [`std::thread::JoinHandle::join`].
+///
+/// [`ThreadPool::spawn`]: struct.ThreadPool.html#method.spawn
+/// [`std::thread::JoinHandle::join`]: https://doc.rust-lang.org/std/thread/struct.JoinHandle.html#method.join
+pub struct JobHandle<T> {
+    result: Receiver<thread::Result<T>>,
+    taken: bool,
+}
+
+impl<T> JobHandle<T> {
+    /// Waits for the job to finish and returns its result.
+    ///
+    /// # Panics
+    ///
+    /// This function will panic if the result was already taken by `try_join`
+    /// or `join_timeout`.
+    pub fn join(mut self) -> thread::Result<T> {
+        self.check_taken();
+        let result = self.result.recv().unwrap_or_else(|_| Err(lost()));
+        self.taken = true;
+        result
+    }
+
+    /// Returns the result of the job if it has finished, without blocking.
+    ///
+    /// # Panics
+    ///
+    /// This function will panic if the result was already taken.
+    pub fn try_join(&mut self) -> Option<thread::Result<T>> {
+        self.check_taken();
+        let result = match self.result.try_recv() {
+            Ok(result) => result,
+            Err(TryRecvError::Empty) => return None,
+            Err(TryRecvError::Disconnected) => Err(lost()),
+        };
+        self.taken = true;
+        Some(result)
+    }
+
+    /// Waits at most `timeout` for the job to finish and returns its result,
+    /// or `None` if the job is still pending.
+    ///
+    /// # Panics
+    ///
+    /// This function will panic if the result was already taken.
+    pub fn join_timeout(&mut self, timeout: Duration) -> Option<thread::Result<T>> {
+        self.check_taken();
+        let result = match self.result.recv_timeout(timeout) {
+            Ok(result) => result,
+            Err(RecvTimeoutError::Timeout) => return None,
+            Err(RecvTimeoutError::Disconnected) => Err(lost()),
+        };
+        self.taken = true;
+        Some(result)
+    }
+
+    fn check_taken(&self) {
+        assert!(!self.taken, "job result was already taken");
+    }
+}
+
+/// Wraps `job` so that its result, or its panic payload, is delivered to the
+/// returned handle.
+pub(crate) fn spawn_thunk<F, T>(job: F) -> (Thunk<'static>, JobHandle<T>)
+    where F: FnOnce() -> T + Send + 'static,
+          T: Send + 'static
+{
+    let (tx, rx) = channel();
+    let thunk: Thunk<'static> = Box::new(move || {
+        run_catching(job, move |result| {
+            let _ = tx.send(result);
+        })
+    });
+    let handle = JobHandle {
+        result: rx,
+        taken: false,
+    };
+    (thunk, handle)
+}
+
+/// Runs `job` and passes its result, or its panic payload, to `deliver`.
+///
+/// If `job` panics, the worker unwinds too once the payload is delivered, so
+/// the panic is counted and the thread is replaced like for any other
+/// panicking job.
+pub(crate) fn run_catching<F, T, D>(job: F, deliver: D)
+    where F: FnOnce() -> T,
+          D: FnOnce(thread::Result<T>)
+{
+    match panic::catch_unwind(AssertUnwindSafe(job)) {
+        Ok(value) => deliver(Ok(value)),
+        Err(payload) => {
+            let message = panic_message(&*payload).unwrap_or("job panicked").to_owned();
+            deliver(Err(payload));
+            panic::resume_unwind(Box::new(message));
+        }
+    }
+}
+
+pub(crate) fn lost() -> Box<dyn Any + Send> {
+    Box::new(JOB_LOST)
+}
+
+/// Returns the message of a panic payload if it is a string.
+pub(crate) fn panic_message(payload: &(dyn Any + Send)) -> Option<&str> {
+    if let Some(message) = payload.downcast_ref::<&'static str>() {
+        Some(message)
+    } else {
+        payload.downcast_ref::<String>().map(|message| &message[..])
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::{testing, ThreadPool};
+    use super::{panic_message, JOB_LOST};
+    use std::sync::mpsc::channel;
+    use std::thread::sleep;
+    use std::time::Duration;
+
+    #[test]
+    fn test_spawn_join() {
+        let pool = ThreadPool::new(2);
+        let handles: Vec<_> = (0..8).map(|i| pool.spawn(move || i * 2)).collect();
+        let results: Vec<_> = handles.into_iter().map(|h| h.join().unwrap()).collect();
+        assert_eq!(results, vec![0, 2, 4, 6, 8, 10, 12, 14]);
+    }
+
+    #[test]
+    fn test_spawn_panic_payload() {
+        let pool = ThreadPool::new(1);
+        let handle = pool.spawn(|| -> () { panic!("boom") });
+        let payload = handle.join().unwrap_err();
+        assert_eq!(panic_message(&*payload), Some("boom"));
+
+        // The worker is replaced and the panic is still counted.
+        assert_eq!(pool.spawn(|| 42).join().unwrap(), 42);
+        assert_eq!(pool.panic_count(), 1);
+    }
+
+    #[test]
+    fn test_try_join_and_timeout() {
+        let pool = ThreadPool::new(1);
+        let (tx, rx) = channel::<()>();
+        let mut handle = pool.spawn(move || rx.recv().unwrap());
+        assert!(handle.try_join().is_none());
+        assert!(handle.join_timeout(Duration::from_millis(50)).is_none());
+
+        tx.send(()).unwrap();
+        assert!(handle.join_timeout(Duration::from_secs(10)).unwrap().is_ok());
+
+        let mut handle = pool.spawn(|| "done");
+        loop {
+            if let Some(result) = handle.try_join() {
+                assert_eq!(result.unwrap(), "done");
+                break;
+            }
+            sleep(Duration::from_millis(10));
+        }
+    }
+
+    #[test]
+    fn test_dropped_job_is_lost() {
+        let (pool, release) = testing::blocked_pool();
+        let handle = pool.spawn(|| 1);
+        drop(pool.shutdown_now());
+        release.send(()).unwrap();
+
+        let payload = handle.join().unwrap_err();
+        assert_eq!(panic_message(&*payload), Some(JOB_LOST));
+    }
+}
@@ -0,0 +1,223 @@
+// Copyright 2014 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Hooks called by worker threads when they start and stop, and around every
+//! job they run.
+
+use std::fmt;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::Arc;
+
+use super::ThreadPoolBuilder;
+
+type Hook = Arc<dyn Fn(usize) + Send + Sync>;
+
+/// The hooks of a pool. Every hook is called with the index of the worker.
+#[derive(Clone, Default)]
+pub(crate) struct Hooks {
+    thread_start: Option<Hook>,
+    thread_stop: Option<Hook>,
+    before_job: Option<Hook>,
+    after_job: Option<Hook>,
+}
+
+/// Calls `hook`, ignoring a panic in it.
+fn call(hook: &Option<Hook>, index: usize) {
+    if let Some(ref hook) = *hook {
+        let _ = panic::catch_unwind(AssertUnwindSafe(|| hook(index)));
+    }
+}
+
+impl Hooks {
+    pub fn thread_start(&self, index: usize) {
+        call(&self.thread_start, index);
+    }
+
+    pub fn thread_stop(&self, index: usize) {
+        call(&self.thread_stop, index);
+    }
+
+    /// Calls the `before_job` hook, and the `after_job` hook once the returned
+    /// guard is dropped, also if the job panics. Jobs that do not run on a
+    /// worker of the pool call neither.
+    pub fn around_job(&self, index: Option<usize>) -> AfterJob<'_> {
+        if let Some(index) = index {
+            call(&self.before_job, index);
+        }
+        AfterJob { hooks: self, index }
+    }
+}
+
+impl fmt::Debug for Hooks {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Hooks")
+            .field("thread_start", &self.thread_start.is_some())
+            .field("thread_stop", &self.thread_stop.is_some())
+            .field("before_job", &self.before_job.is_some())
+            .field("after_job", &self.after_job.is_some())
+            .finish()
+    }
+}
+
+pub(crate) struct AfterJob<'a> {
+    hooks: &'a Hooks,
+    index: Option<usize>,
+}
+
+impl<'a> Drop for AfterJob<'a> {
+    fn drop(&mut self) {
+        if let Some(index) = self.index {
+            call(&self.hooks.after_job, index);
+        }
+    }
+}
+
+impl ThreadPoolBuilder {
+    /// Sets a hook that every worker thread calls with its
+    /// [index](struct.ThreadPool.html#method.current_worker_index) when it
+    /// starts, before it runs any job.
+    ///
+    /// The hook is called by the initial threads, by threads spawned later on
+    /// and by the threads that replace workers whose job panicked, which makes
+    /// it the place to set up thread-local state. A panic in the hook is
+    /// ignored.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use threadpool::ThreadPoolBuilder;
+    /// use std::cell::RefCell;
+    /// use std::sync::mpsc::channel;
+    ///
+    /// thread_local!(static BUFFER: RefCell<Vec<u8>> = RefCell::new(Vec::new()));
+    ///
+    /// let pool = ThreadPoolBuilder::new()
+    ///     .num_threads(2)
+    ///     .on_thread_start(|_| BUFFER.with(|buffer| buffer.borrow_mut().reserve(4096)))
+    ///     .build()
+    ///     .unwrap();
+    ///
+    /// let (tx, rx) = channel();
+    /// pool.execute(move || tx.send(BUFFER.with(|buffer| buffer.borrow().capacity())).unwrap());
+    /// assert!(rx.recv().unwrap() >= 4096);
+    /// ```
+    pub fn on_thread_start<F>(mut self, hook: F) -> ThreadPoolBuilder
+        where F: Fn(usize) + Send + Sync + 'static
+    {
+        self.hooks.thread_start = Some(Arc::new(hook));
+        self
+    }
+
+    /// Sets a hook that every worker thread calls with its index when it
+    /// exits, including workers that exit because their job panicked.
+    ///
+    /// Once [`await_termination`](struct.ThreadPool.html#method.await_termination)
+    /// has returned `true`, the hook has been called by every worker. A panic
+    /// in the hook is ignored.
+    pub fn on_thread_stop<F>(mut self, hook: F) -> ThreadPoolBuilder
+        where F: Fn(usize) + Send + Sync + 'static
+    {
+        self.hooks.thread_stop = Some(Arc::new(hook));
+        self
+    }
+
+    /// Sets a hook that a worker calls with its index before it runs a job.
+    /// Cancelled jobs that are dropped without running do not call it. A
+    /// panic in the hook is ignored.
+    pub fn before_job<F>(mut self, hook: F) -> ThreadPoolBuilder
+        where F: Fn(usize) + Send + Sync + 'static
+    {
+        self.hooks.before_job = Some(Arc::new(hook));
+        self
+    }
+
+    /// Sets a hook that a worker calls with its index after it ran a job, also
+    /// if the job panicked. A panic in the hook is ignored.
+    pub fn after_job<F>(mut self, hook: F) -> ThreadPoolBuilder
+        where F: Fn(usize) + Send + Sync + 'static
+    {
+        self.hooks.after_job = Some(Arc::new(hook));
+        self
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::ThreadPoolBuilder;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::{Arc, Mutex};
+    use std::thread::sleep;
+    use std::time::Duration;
+
+    #[test]
+    fn test_thread_hooks_cover_all_threads() {
+        let started = Arc::new(Mutex::new(Vec::new()));
+        let stopped = Arc::new(Mutex::new(Vec::new()));
+        let mut pool = {
+            let started = started.clone();
+            let stopped = stopped.clone();
+            ThreadPoolBuilder::new()
+                .num_threads(2)
+                .initial_threads(1)
+                .on_thread_start(move |index| started.lock().unwrap().push(index))
+                .on_thread_stop(move |index| stopped.lock().unwrap().push(index))
+                .build()
+                .unwrap()
+        };
+        pool.set_panic_output(false);
+
+        // Replaces a thread.
+        pool.execute(|| panic!("replace me"));
+        pool.join();
+        // Keeps two threads busy.
+        pool.execute(|| sleep(Duration::from_millis(50)));
+        pool.execute(|| sleep(Duration::from_millis(50)));
+        pool.join();
+
+        pool.shutdown();
+        assert!(pool.await_termination(Duration::from_secs(10)));
+        let mut started = started.lock().unwrap().clone();
+        let mut stopped = stopped.lock().unwrap().clone();
+        started.sort();
+        stopped.sort();
+        assert!(started.len() >= 3);
+        assert!(started.contains(&0) && started.contains(&1));
+        assert_eq!(started, stopped);
+    }
+
+    #[test]
+    fn test_job_hooks() {
+        let before = Arc::new(AtomicUsize::new(0));
+        let after = Arc::new(AtomicUsize::new(0));
+        let mut pool = {
+            let before = before.clone();
+            let after = after.clone();
+            ThreadPoolBuilder::new()
+                .num_threads(2)
+                .before_job(move |_| {
+                    before.fetch_add(1, Ordering::SeqCst);
+                })
+                .after_job(move |_| {
+                    after.fetch_add(1, Ordering::SeqCst);
+                })
+                .build()
+                .unwrap()
+        };
+        pool.set_panic_output(false);
+        for _ in 0..10 {
+            pool.execute(|| {});
+        }
+        pool.execute(|| panic!("after_job still runs"));
+        pool.join();
+
+        assert_eq!(before.load(Ordering::SeqCst), 11);
+        assert_eq!(after.load(Ordering::SeqCst), 11);
+    }
+}
@@ -0,0 +1,223 @@
+// Copyright 2014 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Stable indices and names of worker threads.
+//!
+//! Every worker of a pool has an index that no other live worker of the pool
+//! has. A new worker takes the lowest free index, and the replacement of a
+//! worker that panicked takes over the index of the worker it replaces, so the
+//! indices stay below the number of threads.
+
+use std::cell::RefCell;
+use std::sync::{Arc, Mutex};
+
+use super::steal::LocalQueue;
+use super::{ThreadPool, ThreadPoolSharedData};
+
+thread_local! {
+    static CURRENT_WORKER: RefCell<Option<CurrentWorker>> = const { RefCell::new(None) };
+}
+
+/// The worker running on the current thread.
+pub(crate) struct CurrentWorker {
+    // Address of the shared data of the pool, only used for comparison.
+    pool: usize,
+    pub index: usize,
+    pub local: Arc<LocalQueue>,
+}
+
+/// The indices taken by the workers of a pool.
+pub(crate) struct WorkerIndices {
+    taken: Mutex<Vec<bool>>,
+}
+
+impl WorkerIndices {
+    pub fn new() -> WorkerIndices {
+        WorkerIndices { taken: Mutex::new(Vec::new()) }
+    }
+
+    /// Takes the lowest free index.
+    pub fn acquire(&self) -> usize {
+        let mut taken = self.taken.lock().unwrap();
+        match taken.iter().position(|&taken| !taken) {
+            Some(index) => {
+                taken[index] = true;
+                index
+            }
+            None => {
+                taken.push(true);
+                taken.len() - 1
+            }
+        }
+    }
+
+    pub fn release(&self, index: usize) {
+        self.taken.lock().unwrap()[index] = false;
+    }
+}
+
+/// Returns the name of the worker with `index`: `pattern` with the first `{}`
+/// replaced by the index, or `pattern` itself if it has no `{}`.
+pub(crate) fn thread_name(pattern: &str, index: usize) -> String {
+    pattern.replacen("{}", &index.to_string(), 1)
+}
+
+/// Returns the name of a helper thread of the pool, such as its timer: `pattern`
+/// with the first `{}` replaced by `role`, or `pattern` followed by `-role` if
+/// it has no `{}`.
+pub(crate) fn helper_thread_name(pattern: &str, role: &str) -> String {
+    if pattern.contains("{}") {
+        pattern.replacen("{}", role, 1)
+    } else {
+        format!("{}-{}", pattern, role)
+    }
+}
+
+/// Returns the name of a pool as reported in metrics and events: its thread
+/// name without the `{}` of an indexed pattern, or an empty string for
+/// unnamed pools.
+#[cfg(any(feature = "prometheus", feature = "tracing"))]
+pub(crate) fn pool_name(pattern: Option<&str>) -> String {
+    let pattern = pattern.unwrap_or("");
+    match pattern.find("{}") {
+        Some(at) => {
+            let (head, tail) = (&pattern[..at], &pattern[at + 2..]);
+            let head = head.trim_end_matches(['-', '_', '.', ' ']);
+            let tail = tail.trim_start_matches(['-', '_', '.', ' ']);
+            if head.is_empty() || tail.is_empty() {
+                format!("{}{}", head, tail)
+            } else {
+                format!("{}-{}", head, tail)
+            }
+        }
+        None => pattern.to_owned(),
+    }
+}
+
+/// Marks the current thread as the worker of the pool with `index` and the
+/// local queue `local`.
+pub(crate) fn set_current(shared_data: &ThreadPoolSharedData, index: usize, local: Arc<LocalQueue>) {
+    let worker = CurrentWorker {
+        pool: shared_data as *const ThreadPoolSharedData as usize,
+        index,
+        local,
+    };
+    CURRENT_WORKER.with(|current| *current.borrow_mut() = Some(worker));
+}
+
+/// Marks the current thread as no longer being a worker.
+pub(crate) fn clear_current() {
+    CURRENT_WORKER.with(|current| current.borrow_mut().take());
+}
+
+/// Calls `f` with the worker running on the current thread if it is a worker
+/// of the pool, or returns `None` otherwise.
+pub(crate) fn with_current<F, R>(shared_data: &ThreadPoolSharedData, f: F) -> Option<R>
+    where F: FnOnce(&CurrentWorker) -> R
+{
+    let pool = shared_data as *const ThreadPoolSharedData as usize;
+    CURRENT_WORKER.with(|current| match *current.borrow() {
+        Some(ref worker) if worker.pool == pool => Some(f(worker)),
+        _ => None,
+    })
+}
+
+/// Returns the index of the worker running on the current thread if it is a
+/// worker of the pool, or `None` otherwise.
+pub(crate) fn current(shared_data: &ThreadPoolSharedData) -> Option<usize> {
+    with_current(shared_data, |worker| worker.index)
+}
+
+impl ThreadPool {
+    /// Returns the index of the worker running on the current thread, or
+    /// `None` if the current thread is not a worker of any pool.
+    ///
+    /// Indices are between 0 and the number of threads of the pool. While a
+    /// worker is running no other worker of its pool has the same index, and
+    /// a worker that replaces one that panicked takes over its index. Thread
+    /// names with a `{}` pattern include the index, see
+    /// [`ThreadPoolBuilder::thread_name`].
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use threadpool::ThreadPool;
+    ///
+    /// let pool = ThreadPool::new(1);
+    /// let index = pool.spawn(ThreadPool::current_worker_index).join().unwrap();
+    /// assert_eq!(index, Some(0));
+    /// assert_eq!(ThreadPool::current_worker_index(), None);
+    /// ```
+    ///
+    /// [`ThreadPoolBuilder::thread_name`]: struct.ThreadPoolBuilder.html#method.thread_name
+    pub fn current_worker_index() -> Option<usize> {
+        CURRENT_WORKER.with(|current| current.borrow().as_ref().map(|worker| worker.index))
+    }
+
+    /// Returns `true` if the current thread is a worker of any pool.
+    pub fn is_worker_thread() -> bool {
+        ThreadPool::current_worker_index().is_some()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::{ThreadPool, ThreadPoolBuilder};
+    use std::collections::HashSet;
+    use std::sync::mpsc::channel;
+    use std::sync::{Arc, Barrier};
+    use std::thread;
+
+    #[test]
+    fn test_indexed_thread_names() {
+        let pool = ThreadPoolBuilder::new().thread_name("ingest-{}").num_threads(3).build().unwrap();
+        let barrier = Arc::new(Barrier::new(3));
+        let (tx, rx) = channel();
+        for _ in 0..3 {
+            let barrier = barrier.clone();
+            let tx = tx.clone();
+            pool.execute(move || {
+                barrier.wait();
+                let name = thread::current().name().unwrap().to_owned();
+                tx.send((name, ThreadPool::current_worker_index().unwrap())).unwrap();
+            });
+        }
+
+        let workers: HashSet<_> = rx.iter().take(3).collect();
+        let expected: HashSet<_> = (0..3).map(|i| (format!("ingest-{}", i), i)).collect();
+        assert_eq!(workers, expected);
+        assert!(!ThreadPool::is_worker_thread());
+    }
+
+    #[test]
+    fn test_current_index_is_per_pool() {
+        let a = ThreadPool::new(1);
+        let b = ThreadPool::new(1);
+        let (a_data, b_data) = (a.shared_data.clone(), b.shared_data.clone());
+        let indices = a.spawn(move || (super::current(&a_data), super::current(&b_data)))
+            .join()
+            .unwrap();
+        assert_eq!(indices, (Some(0), None));
+        assert_eq!(super::current(&b.shared_data), None);
+    }
+
+    #[test]
+    fn test_replacement_keeps_index() {
+        let mut pool = ThreadPoolBuilder::new().thread_name("w{}").num_threads(1).build().unwrap();
+        pool.set_panic_output(false);
+        pool.execute(|| panic!("replace me"));
+        pool.join();
+        let name = pool.spawn(|| thread::current().name().map(|name| name.to_owned()))
+            .join()
+            .unwrap();
+        assert_eq!(name, Some("w0".to_owned()));
+        assert_eq!(pool.panic_count(), 1);
+    }
+}
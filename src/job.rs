@@ -0,0 +1,248 @@
+// Copyright 2014 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Jobs and the options they are submitted with.
+
+use std::time::Duration;
+
+use super::{handle, CancellationToken, JobHandle, PoolError, Priority, ThreadPool, Thunk};
+
+/// A job waiting in the queue, together with the options it was submitted with.
+pub(crate) struct Job {
+    thunk: Thunk<'static>,
+    token: Option<CancellationToken>,
+    name: Option<String>,
+    time_budget: Option<Duration>,
+}
+
+impl Job {
+    pub fn new(thunk: Thunk<'static>) -> Job {
+        Job::with_token(thunk, None)
+    }
+
+    pub fn with_token(thunk: Thunk<'static>, token: Option<CancellationToken>) -> Job {
+        Job {
+            thunk,
+            token,
+            name: None,
+            time_budget: None,
+        }
+    }
+
+    pub fn name(&self) -> Option<&str> {
+        self.name.as_ref().map(|name| &name[..])
+    }
+
+    pub fn time_budget(&self) -> Option<Duration> {
+        self.time_budget
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.token.as_ref().is_some_and(|token| token.is_cancelled())
+    }
+
+    pub fn run(self) {
+        self.thunk.call_box();
+    }
+}
+
+/// Job configuration, used to submit jobs with options that `execute` does not
+/// offer.
+///
+/// The methods of `JobBuilder` follow the pattern of
+/// [`std::thread::Builder`]: every option is set with a method taking the
+/// builder by value, and the job is finally submitted to a pool with
+/// [`execute`](#method.execute), [`try_execute`](#method.try_execute) or
+/// [`spawn`](#method.spawn).
+///
+/// # Example
+///
+/// ```rust
+/// use threadpool::{CancellationToken, JobBuilder, Priority, ThreadPool};
+///
+/// let pool = ThreadPool::new(2);
+/// let token = CancellationToken::new();
+///
+/// let handle = JobBuilder::new()
+///     .priority(Priority::High)
+///     .cancellation_token(token.clone())
+///     .spawn(&pool, || 6 * 7);
+///
+/// assert_eq!(handle.join().unwrap(), 42);
+/// ```
+///
+/// [`std::thread::Builder`]: https://doc.rust-lang.org/std/thread/struct.Builder.html
+#[derive(Clone, Debug, Default)]
+pub struct JobBuilder {
+    priority: Priority,
+    token: Option<CancellationToken>,
+    name: Option<String>,
+    time_budget: Option<Duration>,
+}
+
+impl JobBuilder {
+    /// Generates the base configuration for a job, from which configuration
+    /// methods can be chained.
+    pub fn new() -> JobBuilder {
+        JobBuilder::default()
+    }
+
+    /// Sets the priority of the job. Defaults to `Priority::Normal`.
+    pub fn priority(mut self, priority: Priority) -> JobBuilder {
+        self.priority = priority;
+        self
+    }
+
+    /// Attaches a cancellation token to the job.
+    ///
+    /// If the token is cancelled before the job starts, the job is dropped
+    /// without running and counted in
+    /// [`ThreadPool::cancelled_count`](struct.ThreadPool.html#method.cancelled_count).
+    pub fn cancellation_token(mut self, token: CancellationToken) -> JobBuilder {
+        self.token = Some(token);
+        self
+    }
+
+    /// Names the job. The name is reported by the
+    /// [`Watchdog`](struct.Watchdog.html) of the pool.
+    pub fn name<S: Into<String>>(mut self, name: S) -> JobBuilder {
+        self.name = Some(name.into());
+        self
+    }
+
+    /// Sets how long the job is expected to run at most.
+    ///
+    /// A job that runs longer is reported by the
+    /// [`Watchdog`](struct.Watchdog.html) of the pool, but is not interrupted.
+    /// Without a watchdog, the budget has no effect.
+    pub fn time_budget(mut self, budget: Duration) -> JobBuilder {
+        self.time_budget = Some(budget);
+        self
+    }
+
+    /// Executes the function `job` on a thread in `pool` with this
+    /// configuration.
+    ///
+    /// # Panics
+    ///
+    /// This function will panic under the same conditions as
+    /// [`ThreadPool::execute`](struct.ThreadPool.html#method.execute).
+    pub fn execute<F>(self, pool: &ThreadPool, job: F)
+        where F: FnOnce() + Send + 'static
+    {
+        self.try_execute(pool, job).unwrap();
+    }
+
+    /// Executes the function `job` on a thread in `pool` with this
+    /// configuration, or returns an error if the pool does not accept it.
+    pub fn try_execute<F>(self, pool: &ThreadPool, job: F) -> Result<(), PoolError>
+        where F: FnOnce() + Send + 'static
+    {
+        self.submit(pool, Box::new(job))
+    }
+
+    /// Executes the function `job` on a thread in `pool` with this
+    /// configuration and returns a [`JobHandle`] to retrieve its return value.
+    ///
+    /// If the job is cancelled before it starts, joining the handle returns an
+    /// error.
+    ///
+    /// # Panics
+    ///
+    /// This function will panic if the pool has been shut down.
+    ///
+    /// [`JobHandle`]: struct.JobHandle.html
+    pub fn spawn<F, T>(self, pool: &ThreadPool, job: F) -> JobHandle<T>
+        where F: FnOnce() -> T + Send + 'static,
+              T: Send + 'static
+    {
+        let (thunk, handle) = handle::spawn_thunk(job);
+        self.submit(pool, thunk).unwrap();
+        handle
+    }
+
+    fn submit(self, pool: &ThreadPool, thunk: Thunk<'static>) -> Result<(), PoolError> {
+        let job = Job {
+            thunk,
+            token: self.token,
+            name: self.name,
+            time_budget: self.time_budget,
+        };
+        pool.execute_job(job, self.priority)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::{testing, CancellationToken, JobBuilder, ThreadPool};
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+    use std::sync::mpsc::channel;
+    use std::sync::Arc;
+    use std::thread::sleep;
+    use std::time::Duration;
+
+    #[test]
+    fn test_cancel_queued_jobs() {
+        let (pool, release) = testing::blocked_pool();
+        let token = CancellationToken::new();
+        let ran = Arc::new(AtomicUsize::new(0));
+        for _ in 0..3 {
+            let ran = ran.clone();
+            JobBuilder::new().cancellation_token(token.clone()).execute(&pool, move || {
+                ran.fetch_add(1, Ordering::SeqCst);
+            });
+        }
+        let handle = JobBuilder::new().cancellation_token(token.clone()).spawn(&pool, || 1);
+        {
+            let ran = ran.clone();
+            pool.execute(move || {
+                ran.fetch_add(1, Ordering::SeqCst);
+            });
+        }
+
+        token.cancel();
+        release.send(()).unwrap();
+        pool.join();
+
+        assert_eq!(ran.load(Ordering::SeqCst), 1);
+        assert!(handle.join().is_err());
+        assert_eq!(pool.cancelled_count(), 4);
+        assert_eq!(pool.completed_count(), 2);
+        assert_eq!(pool.panic_count(), 0);
+    }
+
+    #[test]
+    fn test_cancel_running_job() {
+        let pool = ThreadPool::new(1);
+        let token = CancellationToken::new();
+        let stopped = Arc::new(AtomicBool::new(false));
+        let (started_tx, started_rx) = channel();
+        {
+            let token = token.clone();
+            let stopped = stopped.clone();
+            JobBuilder::new().cancellation_token(token.clone()).execute(&pool, move || {
+                started_tx.send(()).unwrap();
+                while !token.is_cancelled() {
+                    sleep(Duration::from_millis(1));
+                }
+                stopped.store(true, Ordering::SeqCst);
+            });
+        }
+
+        started_rx.recv().unwrap();
+        token.cancel();
+        pool.join();
+
+        // A job that already started runs to completion.
+        assert!(stopped.load(Ordering::SeqCst));
+        assert_eq!(pool.cancelled_count(), 0);
+        assert_eq!(pool.completed_count(), 1);
+    }
+}
@@ -0,0 +1,253 @@
+// Copyright 2014 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Fork-join of two closures.
+
+use std::mem;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::Ordering;
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use super::steal::WorkerQueues;
+use super::{handle, index, offer_to_pool, run_job, Job, Priority, ThreadPool, Thunk};
+
+/// How long a worker waiting in `join2` sleeps when it found no other job to
+/// run, before it looks again.
+const HELP_INTERVAL_MS: u64 = 1;
+
+/// The result of the closure that `join2` hands to the pool.
+struct JoinState<T> {
+    result: Mutex<Option<thread::Result<T>>>,
+    done: Condvar,
+}
+
+impl<T> JoinState<T> {
+    fn is_done(&self) -> bool {
+        self.result.lock().unwrap().is_some()
+    }
+
+    fn wait_timeout(&self, timeout: Duration) {
+        let result = self.result.lock().unwrap();
+        if result.is_none() {
+            let _ = self.done.wait_timeout(result, timeout).unwrap();
+        }
+    }
+
+    fn wait(&self) -> thread::Result<T> {
+        let mut result = self.result.lock().unwrap();
+        loop {
+            if let Some(result) = result.take() {
+                return result;
+            }
+            result = self.done.wait(result).unwrap();
+        }
+    }
+}
+
+/// The closure handed to the pool. Like a scoped job, it is always dropped
+/// before its result is published, so its borrows end before `join2` returns.
+struct ForkedJob<F, T> {
+    job: Option<F>,
+    state: Arc<JoinState<T>>,
+    result: Option<thread::Result<T>>,
+}
+
+impl<F: FnOnce() -> T, T> ForkedJob<F, T> {
+    fn run(mut self) {
+        let job = self.job.take().unwrap();
+        handle::run_catching(job, move |result| {
+            self.result = Some(result);
+            drop(self);
+        });
+    }
+}
+
+impl<F, T> Drop for ForkedJob<F, T> {
+    fn drop(&mut self) {
+        drop(self.job.take());
+        let result = self.result.take().unwrap_or_else(|| Err(handle::lost()));
+        *self.state.result.lock().unwrap() = Some(result);
+        self.state.done.notify_all();
+    }
+}
+
+impl ThreadPool {
+    /// Runs `a` on the current thread and `b` on the pool, and returns both
+    /// results once both have finished. Both closures may borrow from the
+    /// current stack.
+    ///
+    /// When called from a job running in the same pool, the current worker
+    /// does not block while `b` is pending: it runs `b` itself if no other
+    /// worker has taken it yet, and other queued jobs while `b` is running
+    /// elsewhere. This makes `join2` suitable for recursive divide and
+    /// conquer.
+    ///
+    /// If the queue of the pool is full, `b` runs on the current thread after
+    /// `a`, whatever the [rejection policy] of the pool.
+    ///
+    /// If either closure panics, the panic is propagated once both have
+    /// finished, the one of `a` first.
+    ///
+    /// # Panics
+    ///
+    /// This function will panic if the pool has been shut down.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use threadpool::ThreadPool;
+    ///
+    /// fn sum(pool: &ThreadPool, values: &[u64]) -> u64 {
+    ///     if values.len() <= 1024 {
+    ///         return values.iter().sum();
+    ///     }
+    ///     let (left, right) = values.split_at(values.len() / 2);
+    ///     let (a, b) = pool.join2(|| sum(pool, left), || sum(pool, right));
+    ///     a + b
+    /// }
+    ///
+    /// let pool = ThreadPool::new(4);
+    /// let values: Vec<u64> = (0..100_000).collect();
+    /// assert_eq!(sum(&pool, &values), 4_999_950_000);
+    /// ```
+    ///
+    /// [rejection policy]: enum.RejectionPolicy.html
+    pub fn join2<A, B, RA, RB>(&self, a: A, b: B) -> (RA, RB)
+        where A: FnOnce() -> RA,
+              B: FnOnce() -> RB + Send,
+              RB: Send
+    {
+        let state = Arc::new(JoinState {
+            result: Mutex::new(None),
+            done: Condvar::new(),
+        });
+        let forked = ForkedJob {
+            job: Some(b),
+            state: state.clone(),
+            result: None,
+        };
+        let thunk: Thunk = Box::new(move || forked.run());
+        // `join2` does not return before the job has been run or dropped, so
+        // treating its borrows as `'static` is sound.
+        let thunk = unsafe { mem::transmute::<Thunk, Thunk<'static>>(thunk) };
+        let refused = offer_to_pool(&self.shared_data, Job::new(thunk), Priority::Normal).unwrap();
+
+        let result_a = panic::catch_unwind(AssertUnwindSafe(a));
+        if let Some(job) = refused {
+            job.run();
+        }
+
+        let shared_data = &*self.shared_data;
+        if let Some(worker) = index::current(shared_data) {
+            while !state.is_done() {
+                match WorkerQueues::try_next_job(shared_data) {
+                    Some(job) => {
+                        let result = panic::catch_unwind(AssertUnwindSafe(|| run_job(shared_data, Some(worker), job)));
+                        if result.is_err() {
+                            shared_data.panic_count.fetch_add(1, Ordering::SeqCst);
+                        }
+                    }
+                    None => state.wait_timeout(Duration::from_millis(HELP_INTERVAL_MS)),
+                }
+            }
+        }
+        let result_b = state.wait();
+
+        match (result_a, result_b) {
+            (Ok(a), Ok(b)) => (a, b),
+            (Err(payload), _) | (_, Err(payload)) => panic::resume_unwind(payload),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::{testing, RejectionPolicy, ThreadPool};
+    use std::panic::{self, AssertUnwindSafe};
+    use std::thread::{self, sleep};
+    use std::time::Duration;
+
+    fn fib(pool: &ThreadPool, n: u64) -> u64 {
+        if n < 2 {
+            return n;
+        }
+        let (a, b) = pool.join2(|| fib(pool, n - 1), || fib(pool, n - 2));
+        a + b
+    }
+
+    #[test]
+    fn test_join2_borrows() {
+        let pool = ThreadPool::new(2);
+        let mut left = vec![1, 2, 3];
+        let mut right = vec![4, 5, 6];
+        let (a, b) = pool.join2(|| {
+                                    left.push(0);
+                                    left.len()
+                                },
+                                || {
+                                    right.clear();
+                                    thread::current().id()
+                                });
+        assert_eq!(a, 4);
+        assert!(b != thread::current().id());
+        assert!(right.is_empty());
+    }
+
+    #[test]
+    fn test_join2_recursive_on_single_thread() {
+        // Every level blocks a worker unless the waiting worker runs the
+        // queued halves itself.
+        let pool = ThreadPool::new(1);
+        let result = {
+            let inner_pool = pool.clone();
+            pool.spawn(move || fib(&inner_pool, 15)).join().unwrap()
+        };
+        assert_eq!(result, 610);
+        pool.join();
+        assert_eq!(pool.panic_count(), 0);
+    }
+
+    #[test]
+    fn test_join2_propagates_panic() {
+        let pool = ThreadPool::new(2);
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            pool.join2(|| sleep(Duration::from_millis(10)), || panic!("join boom"))
+        }));
+        let payload = result.unwrap_err();
+        assert_eq!(payload.downcast_ref::<&str>(), Some(&"join boom"));
+        pool.join();
+        assert_eq!(pool.panic_count(), 1);
+    }
+
+    #[test]
+    fn test_join2_runs_refused_job_inline() {
+        let policies = [RejectionPolicy::Block,
+                        RejectionPolicy::Abort,
+                        RejectionPolicy::CallerRuns,
+                        RejectionPolicy::DiscardOldest,
+                        RejectionPolicy::DiscardNewest];
+        for &policy in &policies {
+            let (mut pool, release) = testing::blocked_pool();
+            pool.set_queue_capacity(Some(1));
+            pool.set_rejection_policy(policy);
+            pool.execute(|| ());
+
+            let (a, b) = pool.join2(|| 1, || thread::current().id());
+            assert_eq!(a, 1);
+            assert_eq!(b, thread::current().id());
+
+            release.send(()).unwrap();
+            pool.join();
+            assert_eq!(pool.completed_count(), 2);
+        }
+    }
+}
@@ -10,10 +10,63 @@
 
 //! Abstraction of a thread pool for basic parallelism.
 
-use std::sync::mpsc::{channel, Sender, Receiver};
-use std::sync::{Arc, Mutex};
-use std::sync::atomic::{AtomicUsize, Ordering};
+#[cfg(feature = "tracing")]
+extern crate tracing;
+
+use std::io;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{Arc, Condvar, Mutex};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
 use std::thread::{Builder, panicking};
+use std::time::{Duration, Instant};
+
+mod builder;
+mod cancel;
+mod error;
+mod handle;
+mod hooks;
+mod index;
+mod job;
+mod join;
+mod map;
+mod panics;
+#[cfg(feature = "prometheus")]
+mod prometheus;
+mod queue;
+mod ring;
+mod schedule;
+mod scope;
+mod stats;
+mod steal;
+#[cfg(test)]
+mod testing;
+mod trace;
+mod watchdog;
+
+pub use builder::ThreadPoolBuilder;
+pub use cancel::CancellationToken;
+pub use error::{ConfigError, PoolError};
+pub use handle::JobHandle;
+pub use job::JobBuilder;
+pub use map::{Map, MapUnordered};
+pub use panics::{PanicInfo, PanicPolicy};
+#[cfg(feature = "prometheus")]
+pub use prometheus::MetricsServer;
+pub use queue::{JobQueue, PendingJob, Priority, RejectionPolicy};
+pub use ring::RingQueue;
+pub use schedule::ScheduleHandle;
+pub use scope::Scope;
+pub use stats::PoolStats;
+pub use watchdog::{OverdueJob, Watchdog};
+
+use hooks::Hooks;
+use index::WorkerIndices;
+use job::Job;
+use panics::PanicLog;
+use queue::{Injector, PushError};
+use schedule::Scheduler;
+use steal::{Worker, WorkerQueues};
+use watchdog::JobMonitor;
 
 trait FnBox {
     fn call_box(self: Box<Self>);
@@ -25,36 +78,20 @@ impl<F: FnOnce()> FnBox for F {
     }
 }
 
-type Thunk<'a> = Box<FnBox + Send + 'a>;
+type Thunk<'a> = Box<dyn FnBox + Send + 'a>;
 
 struct Sentinel<'a> {
-    name: Option<String>,
-    jobs: &'a Arc<Mutex<Receiver<Thunk<'static>>>>,
-    thread_counter: &'a Arc<AtomicUsize>,
-    thread_count_spawned: &'a Arc<AtomicUsize>,
-    thread_count_min: &'a Arc<AtomicUsize>,
-    thread_count_max: &'a Arc<AtomicUsize>,
-    thread_count_panic: &'a Arc<AtomicUsize>,
+    shared_data: &'a Arc<ThreadPoolSharedData>,
+    index: usize,
     active: bool,
 }
 
 impl<'a> Sentinel<'a> {
-    fn new(name: Option<String>,
-           jobs: &'a Arc<Mutex<Receiver<Thunk<'static>>>>,
-           thread_counter: &'a Arc<AtomicUsize>,
-           thread_count_spawned: &'a Arc<AtomicUsize>,
-           thread_count_min: &'a Arc<AtomicUsize>,
-           thread_count_max: &'a Arc<AtomicUsize>,
-           thread_count_panic: &'a Arc<AtomicUsize>)
-           -> Sentinel<'a> {
+    #[allow(clippy::redundant_field_names)]
+    fn new(shared_data: &'a Arc<ThreadPoolSharedData>, index: usize) -> Sentinel<'a> {
         Sentinel {
-            name: name,
-            jobs: jobs,
-            thread_counter: thread_counter,
-            thread_count_spawned: thread_count_spawned,
-            thread_count_min: thread_count_min,
-            thread_count_max: thread_count_max,
-            thread_count_panic: thread_count_panic,
+            shared_data: shared_data,
+            index: index,
             active: true,
         }
     }
@@ -68,23 +105,152 @@ impl<'a> Sentinel<'a> {
 impl<'a> Drop for Sentinel<'a> {
     fn drop(&mut self) {
         if self.active {
+            // The job that panicked never got to mark itself as finished.
             if panicking() {
-                self.thread_count_panic.fetch_add(1, Ordering::SeqCst);
+                self.shared_data.panic_count.fetch_add(1, Ordering::SeqCst);
             }
-            if self.thread_counter.fetch_sub(1, Ordering::SeqCst) == 1 &&
-               self.thread_count_spawned.load(Ordering::Acquire) <=
-               self.thread_count_min.load(Ordering::Relaxed) {
-                self.thread_count_spawned.fetch_add(1, Ordering::SeqCst);
-                spawn_in_pool(self.name.clone(),
-                              self.jobs.clone(),
-                              self.thread_counter.clone(),
-                              self.thread_count_spawned.clone(),
-                              self.thread_count_min.clone(),
-                              self.thread_count_max.clone(),
-                              self.thread_count_panic.clone());
+            self.shared_data.hooks.thread_stop(self.index);
+            self.shared_data.threads_retired.fetch_add(1, Ordering::SeqCst);
+            self.shared_data.active_count.fetch_sub(1, Ordering::SeqCst);
+            self.shared_data.no_work_notify_all();
+            // The replacement takes over the place of this thread, so the
+            // number of spawned threads stays the same unless it cannot be
+            // created. A pool shut down by the panic is not replenished.
+            // It also takes over the index of this thread.
+            let respawn = self.shared_data.panic_log.policy() != PanicPolicy::Shutdown;
+            trace::worker_panicked(self.shared_data, self.index, respawn);
+            let mut respawned = false;
+            if respawn {
+                match spawn_worker(self.shared_data.clone(), self.index) {
+                    Ok(()) => respawned = true,
+                    Err(error) => trace::spawn_failed(self.shared_data, self.index, &error),
+                }
             }
+            if !respawned {
+                self.shared_data.worker_indices.release(self.index);
+                self.shared_data.spawned_count.fetch_sub(1, Ordering::SeqCst);
+                self.shared_data.terminated_notify_all();
+            }
+        }
+    }
+}
+
+struct ThreadPoolSharedData {
+    name: Option<String>,
+    hooks: Hooks,
+    stack_size: Option<usize>,
+    // How long idle workers above `min_count` wait for a job, 0 if forever.
+    keep_alive_nanos: AtomicU64,
+    job_queue: Injector,
+    worker_indices: WorkerIndices,
+    worker_queues: WorkerQueues,
+    scheduler: Scheduler,
+    job_monitor: JobMonitor,
+    panic_log: PanicLog,
+    handle_count: AtomicUsize,
+    empty_trigger: Mutex<()>,
+    empty_condvar: Condvar,
+    terminated_trigger: Mutex<()>,
+    terminated_condvar: Condvar,
+    join_generation: AtomicUsize,
+    queued_count: AtomicUsize,
+    active_count: AtomicUsize,
+    spawned_count: AtomicUsize,
+    min_count: AtomicUsize,
+    max_count: AtomicUsize,
+    compensating_count: AtomicUsize,
+    panic_count: AtomicUsize,
+    joined_panic_count: AtomicUsize,
+    completed_count: AtomicUsize,
+    cancelled_count: AtomicUsize,
+    submitted_count: AtomicUsize,
+    threads_spawned: AtomicUsize,
+    threads_retired: AtomicUsize,
+    peak_queued: AtomicUsize,
+    peak_active: AtomicUsize,
+    #[cfg(feature = "prometheus")]
+    job_durations: prometheus::JobDurations,
+    #[cfg(feature = "tracing")]
+    tracer: trace::Tracer,
+}
+
+impl ThreadPoolSharedData {
+    fn has_work(&self) -> bool {
+        self.queued_count.load(Ordering::SeqCst) > 0 || self.active_count.load(Ordering::SeqCst) > 0
+    }
+
+    /// Notify all observers joining this pool if there is no more work to do.
+    fn no_work_notify_all(&self) {
+        if !self.has_work() {
+            let _lock = self.empty_trigger.lock().unwrap();
+            self.empty_condvar.notify_all();
+        }
+    }
+
+    /// The maximum number of threads, raised by one for every overdue job the
+    /// watchdog compensates for.
+    fn thread_limit(&self) -> usize {
+        self.max_count.load(Ordering::Relaxed) + self.compensating_count.load(Ordering::Relaxed)
+    }
+
+    /// Counts `count` jobs that are about to be queued as submitted and
+    /// queued. Counting them before they can be taken keeps a worker from
+    /// finishing a job that is not counted yet.
+    fn add_submitted(&self, count: usize) {
+        self.submitted_count.fetch_add(count, Ordering::SeqCst);
+        let queued = self.queued_count.fetch_add(count, Ordering::SeqCst) + count;
+        self.peak_queued.fetch_max(queued, Ordering::SeqCst);
+    }
+
+    /// Takes back the counts of `count` jobs that were not queued after all.
+    fn remove_submitted(&self, count: usize) {
+        self.queued_count.fetch_sub(count, Ordering::SeqCst);
+        self.submitted_count.fetch_sub(count, Ordering::SeqCst);
+    }
+
+    /// Gives up the place of a worker, unless no more than `floor` threads are
+    /// left.
+    fn try_retire(&self, floor: usize) -> bool {
+        self.spawned_count
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |spawned| if spawned > floor {
+                Some(spawned - 1)
+            } else {
+                None
+            })
+            .is_ok()
+    }
+
+    fn keep_alive(&self) -> Option<Duration> {
+        match self.keep_alive_nanos.load(Ordering::Relaxed) {
+            0 => None,
+            nanos => Some(Duration::from_nanos(nanos)),
         }
     }
+
+    fn is_terminated(&self) -> bool {
+        self.job_queue.is_closed() && self.spawned_count.load(Ordering::SeqCst) == 0
+    }
+
+    /// Notify all observers awaiting termination if the last worker has exited.
+    fn terminated_notify_all(&self) {
+        if self.is_terminated() {
+            let _lock = self.terminated_trigger.lock().unwrap();
+            self.terminated_condvar.notify_all();
+        }
+    }
+
+    fn shutdown_now(&self) -> Vec<PendingJob> {
+        self.job_queue.close();
+        self.scheduler.close();
+        self.job_monitor.close();
+        let mut pending = self.job_queue.drain();
+        pending.extend(self.worker_queues.drain().into_iter().map(|job| PendingJob::new(job, Priority::Normal)));
+        self.queued_count.fetch_sub(pending.len(), Ordering::SeqCst);
+        trace::shut_down(self, Some(pending.len()));
+        self.no_work_notify_all();
+        self.terminated_notify_all();
+        pending
+    }
 }
 
 /// A thread pool used to execute functions in parallel.
@@ -153,20 +319,27 @@ impl<'a> Drop for Sentinel<'a> {
 /// barrier.wait();
 /// assert_eq!(an_atomic.load(Ordering::SeqCst), 23);
 /// ```
-#[derive(Clone)]
 pub struct ThreadPool {
     // How the threadpool communicates with subthreads.
     //
-    // This is the only such Sender, so when it is dropped all subthreads will
-    // quit.
-    name: Option<String>,
-    jobs: Sender<Thunk<'static>>,
-    job_receiver: Arc<Mutex<Receiver<Thunk<'static>>>>,
-    active_count: Arc<AtomicUsize>,
-    spawned_count: Arc<AtomicUsize>,
-    min_count: Arc<AtomicUsize>,
-    max_count: Arc<AtomicUsize>,
-    panic_count: Arc<AtomicUsize>,
+    // When the last clone of the pool is dropped, the job queue is closed and
+    // all subthreads quit once it has been drained.
+    shared_data: Arc<ThreadPoolSharedData>,
+}
+
+impl Clone for ThreadPool {
+    fn clone(&self) -> ThreadPool {
+        self.shared_data.handle_count.fetch_add(1, Ordering::SeqCst);
+        ThreadPool { shared_data: self.shared_data.clone() }
+    }
+}
+
+impl Drop for ThreadPool {
+    fn drop(&mut self) {
+        if self.shared_data.handle_count.fetch_sub(1, Ordering::SeqCst) == 1 {
+            self.shutdown();
+        }
+    }
 }
 
 impl ThreadPool {
@@ -176,7 +349,28 @@ impl ThreadPool {
     ///
     /// This function will panic if `num_threads` is 0.
     pub fn new(num_threads: usize) -> ThreadPool {
-        ThreadPool::new_pool(None, num_threads, num_threads)
+        ThreadPoolBuilder::new().num_threads(num_threads).build().unwrap()
+    }
+
+    /// Spawns a new thread pool with `num_threads` threads, or returns an error
+    /// if `num_threads` is 0 or the threads could not be spawned.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use threadpool::{PoolError, ThreadPool};
+    ///
+    /// assert!(ThreadPool::try_new(4).is_ok());
+    /// match ThreadPool::try_new(0) {
+    ///     Err(PoolError::InvalidThreadCount) => {}
+    ///     _ => panic!("a pool needs at least one thread"),
+    /// }
+    /// ```
+    pub fn try_new(num_threads: usize) -> Result<ThreadPool, PoolError> {
+        ThreadPoolBuilder::new().num_threads(num_threads).build().map_err(|err| match err {
+            ConfigError::SpawnFailed(err) => PoolError::SpawnFailed(err),
+            _ => PoolError::InvalidThreadCount,
+        })
     }
 
     /// Spawns a new dynamic thread pool with `num_threads` maximum threads and
@@ -192,11 +386,16 @@ impl ThreadPool {
     /// This function will panic if `num_threads` or `num_initial_threads` is 0,
     /// or if `num_initial_threads` is greater than `num_threads`.
     pub fn new_dynamic(num_threads: usize, num_initial_threads: usize) -> ThreadPool {
-        ThreadPool::new_pool(None, num_threads, num_initial_threads)
+        ThreadPoolBuilder::new()
+            .num_threads(num_threads)
+            .initial_threads(num_initial_threads)
+            .build()
+            .unwrap()
     }
 
     /// Spawns a new thread pool with `num_threads` threads. Each thread will have the
-    /// [name][thread name] `name`.
+    /// [name][thread name] `name`, with a `{}` in it replaced by the
+    /// [index](#method.current_worker_index) of the thread.
     ///
     /// # Panics
     ///
@@ -226,7 +425,7 @@ impl ThreadPool {
     ///
     /// [thread name]: https://doc.rust-lang.org/std/thread/struct.Thread.html#method.name
     pub fn new_with_name(name: String, num_threads: usize) -> ThreadPool {
-        ThreadPool::new_pool(Some(name), num_threads, num_threads)
+        ThreadPoolBuilder::new().thread_name(name).num_threads(num_threads).build().unwrap()
     }
 
     /// Spawns a new dynamic thread pool with `num_threads` maximum threads and
@@ -240,92 +439,255 @@ impl ThreadPool {
                                  num_threads: usize,
                                  num_initial_threads: usize)
                                  -> ThreadPool {
-        ThreadPool::new_pool(Some(name), num_threads, num_initial_threads)
+        ThreadPoolBuilder::new()
+            .thread_name(name)
+            .num_threads(num_threads)
+            .initial_threads(num_initial_threads)
+            .build()
+            .unwrap()
     }
 
-    #[inline]
-    fn new_pool(name: Option<String>,
-                num_threads: usize,
-                num_initial_threads: usize)
-                -> ThreadPool {
-        assert!(num_threads >= 1);
-        assert!(num_initial_threads >= 1);
-        assert!(num_initial_threads <= num_threads);
-
-        let (tx, rx) = channel::<Thunk<'static>>();
-        let rx = Arc::new(Mutex::new(rx));
-        let active_count = Arc::new(AtomicUsize::new(0));
-        let spawned_count = Arc::new(AtomicUsize::new(0));
-        let min_count = Arc::new(AtomicUsize::new(num_initial_threads));
-        let max_count = Arc::new(AtomicUsize::new(num_threads));
-        let panic_count = Arc::new(AtomicUsize::new(0));
-
-        // Threadpool threads
-        for _ in 0..num_initial_threads {
-            spawned_count.fetch_add(1, Ordering::SeqCst);
-            spawn_in_pool(name.clone(),
-                          rx.clone(),
-                          active_count.clone(),
-                          spawned_count.clone(),
-                          min_count.clone(),
-                          max_count.clone(),
-                          panic_count.clone());
-        }
-
-        ThreadPool {
-            name: name,
-            jobs: tx,
-            job_receiver: rx.clone(),
-            active_count: active_count,
-            spawned_count: spawned_count,
-            min_count: min_count,
-            max_count: max_count,
-            panic_count: panic_count,
-        }
+    /// Spawns a new thread pool with `num_threads` threads, whose jobs wait in
+    /// `queue` until a worker takes them.
+    ///
+    /// Use [`ThreadPoolBuilder::job_queue`] to combine a custom queue with
+    /// other settings.
+    ///
+    /// # Panics
+    ///
+    /// This function will panic if `num_threads` is 0.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use threadpool::{RingQueue, ThreadPool};
+    ///
+    /// let pool = ThreadPool::with_job_queue(4, RingQueue::new(1024));
+    /// pool.execute(|| println!("hello from a ring"));
+    /// pool.join();
+    /// ```
+    ///
+    /// [`ThreadPoolBuilder::job_queue`]: struct.ThreadPoolBuilder.html#method.job_queue
+    pub fn with_job_queue<Q>(num_threads: usize, queue: Q) -> ThreadPool
+        where Q: JobQueue + 'static
+    {
+        ThreadPoolBuilder::new().num_threads(num_threads).job_queue(queue).build().unwrap()
     }
 
     /// Executes the function `job` on a thread in the pool.
+    ///
+    /// When called from a job running in the same pool, `job` is queued on the
+    /// current worker, which runs it once it is idle unless another worker
+    /// steals it first.
+    ///
+    /// # Panics
+    ///
+    /// This function will panic if the pool has been shut down or has no
+    /// threads left and cannot spawn new ones. Use
+    /// [`try_execute`](#method.try_execute) to handle those cases instead.
     pub fn execute<F>(&self, job: F)
         where F: FnOnce() + Send + 'static
     {
-        // Spawn a new thread if the pool is dynamically managed and the number
-        // of active threads is smaller than the maximum allowed.
-        if self.spawned_count.load(Ordering::Acquire) < self.max_count.load(Ordering::Relaxed) {
-            self.spawned_count.fetch_add(1, Ordering::SeqCst);
-            spawn_in_pool(self.name.clone(),
-                          self.job_receiver.clone(),
-                          self.active_count.clone(),
-                          self.spawned_count.clone(),
-                          self.min_count.clone(),
-                          self.max_count.clone(),
-                          self.panic_count.clone());
+        self.try_execute(job).unwrap();
+    }
+
+    /// Executes the function `job` on a thread in the pool, or returns an
+    /// error if the pool does not accept it.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use threadpool::{PoolError, ThreadPool};
+    ///
+    /// let pool = ThreadPool::new(2);
+    /// assert!(pool.try_execute(|| println!("hello")).is_ok());
+    ///
+    /// pool.shutdown();
+    /// match pool.try_execute(|| println!("too late")) {
+    ///     Err(PoolError::ShutDown) => {}
+    ///     _ => panic!("the pool should refuse new jobs"),
+    /// }
+    /// ```
+    pub fn try_execute<F>(&self, job: F) -> Result<(), PoolError>
+        where F: FnOnce() + Send + 'static
+    {
+        self.execute_job(Job::new(Box::new(job)), Priority::Normal)
+    }
+
+    /// Executes every function in `jobs` on a thread in the pool and returns
+    /// how many of them were accepted.
+    ///
+    /// All jobs are added to the queue in one operation, and only as many
+    /// idle workers are woken as there are jobs. Jobs that do not fit into a
+    /// bounded queue are submitted one at a time and handled according to the
+    /// [rejection policy](#method.set_rejection_policy). Returns 0 if the pool
+    /// has been shut down.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use threadpool::ThreadPool;
+    /// use std::sync::atomic::{AtomicUsize, Ordering};
+    /// use std::sync::Arc;
+    ///
+    /// let pool = ThreadPool::new(4);
+    /// let counter = Arc::new(AtomicUsize::new(0));
+    /// let jobs = (0..1000).map(|_| {
+    ///     let counter = counter.clone();
+    ///     move || {
+    ///         counter.fetch_add(1, Ordering::Relaxed);
+    ///     }
+    /// });
+    ///
+    /// assert_eq!(pool.execute_batch(jobs), 1000);
+    /// pool.join();
+    /// assert_eq!(counter.load(Ordering::Relaxed), 1000);
+    /// ```
+    pub fn execute_batch<I, F>(&self, jobs: I) -> usize
+        where I: IntoIterator<Item = F>,
+              F: FnOnce() + Send + 'static
+    {
+        let jobs: Vec<Job> = jobs.into_iter().map(|job| Job::new(Box::new(job))).collect();
+        let count = jobs.len();
+        let shared_data = &self.shared_data;
+        if count == 0 || grow_pool(shared_data, count).is_err() {
+            return 0;
+        }
+        shared_data.add_submitted(count);
+        match shared_data.job_queue.push_batch(jobs, Priority::Normal) {
+            Ok((pushed, rest)) => {
+                shared_data.remove_submitted(rest.len());
+                let submitted = rest.into_iter()
+                    .map(|job| execute_in_pool(shared_data, job, Priority::Normal, true))
+                    .filter(Result::is_ok)
+                    .count();
+                pushed + submitted
+            }
+            Err(_) => {
+                shared_data.remove_submitted(count);
+                0
+            }
         }
-        self.jobs.send(Box::new(move || job())).unwrap();
+    }
+
+    /// Executes the function `job` on a thread in the pool with the given
+    /// `priority`.
+    ///
+    /// Queued jobs with a higher priority are started first, but jobs that
+    /// have been waiting for a long time gain priority, see
+    /// [`set_aging_interval`](#method.set_aging_interval).
+    ///
+    /// # Panics
+    ///
+    /// This function will panic under the same conditions as `execute`.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use threadpool::{Priority, ThreadPool};
+    ///
+    /// let pool = ThreadPool::new(4);
+    /// pool.execute_with_priority(Priority::Low, || println!("whenever"));
+    /// pool.execute_with_priority(Priority::High, || println!("right away"));
+    /// pool.join();
+    /// ```
+    pub fn execute_with_priority<F>(&self, priority: Priority, job: F)
+        where F: FnOnce() + Send + 'static
+    {
+        self.try_execute_with_priority(priority, job).unwrap();
+    }
+
+    /// Executes the function `job` on a thread in the pool with the given
+    /// `priority`, or returns an error if the pool does not accept it.
+    pub fn try_execute_with_priority<F>(&self, priority: Priority, job: F) -> Result<(), PoolError>
+        where F: FnOnce() + Send + 'static
+    {
+        self.execute_job(Job::new(Box::new(job)), priority)
+    }
+
+    fn execute_job(&self, job: Job, priority: Priority) -> Result<(), PoolError> {
+        execute_in_pool(&self.shared_data, job, priority, true)
+    }
+
+    /// Executes the function `job` on a thread in the pool and returns a
+    /// [`JobHandle`] that can be used to retrieve its return value.
+    ///
+    /// If `job` panics, the panic payload is delivered to the handle and the
+    /// worker thread is replaced as if the job had been submitted with `execute`.
+    ///
+    /// # Panics
+    ///
+    /// This function will panic if the pool has been shut down.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use threadpool::ThreadPool;
+    ///
+    /// let pool = ThreadPool::new(2);
+    /// let handle = pool.spawn(|| 6 * 7);
+    /// assert_eq!(handle.join().unwrap(), 42);
+    /// ```
+    ///
+    /// [`JobHandle`]: struct.JobHandle.html
+    pub fn spawn<F, T>(&self, job: F) -> JobHandle<T>
+        where F: FnOnce() -> T + Send + 'static,
+              T: Send + 'static
+    {
+        JobBuilder::new().spawn(self, job)
     }
 
     /// Returns the number of currently active threads.
     pub fn active_count(&self) -> usize {
-        self.active_count.load(Ordering::Relaxed)
+        self.shared_data.active_count.load(Ordering::Relaxed)
     }
 
     /// Returns the number of spawned threads.
     pub fn spawned_count(&self) -> usize {
-        self.spawned_count.load(Ordering::Relaxed)
+        self.shared_data.spawned_count.load(Ordering::Relaxed)
     }
 
     /// Returns the minimum number of created threads.
     pub fn min_count(&self) -> usize {
-        self.min_count.load(Ordering::Relaxed)
+        self.shared_data.min_count.load(Ordering::Relaxed)
     }
 
     /// Returns the maximum number of created threads.
     pub fn max_count(&self) -> usize {
-        self.max_count.load(Ordering::Relaxed)
+        self.shared_data.max_count.load(Ordering::Relaxed)
+    }
+
+    /// Returns the maximum number of jobs that may wait in the queue, or `None`
+    /// if the queue is unbounded.
+    pub fn queue_capacity(&self) -> Option<usize> {
+        self.shared_data.job_queue.capacity()
+    }
+
+    /// Returns what the pool does with new jobs while its queue is full.
+    pub fn rejection_policy(&self) -> RejectionPolicy {
+        self.shared_data.job_queue.policy()
+    }
+
+    /// Returns the time after which a queued job gains one level of priority.
+    pub fn aging_interval(&self) -> Duration {
+        self.shared_data.job_queue.aging_interval()
+    }
+
+    /// Returns the number of jobs that ran to completion without panicking over
+    /// the lifetime of the pool.
+    pub fn completed_count(&self) -> usize {
+        self.shared_data.completed_count.load(Ordering::Relaxed)
+    }
+
+    /// Returns the number of jobs that were dropped without running because
+    /// their cancellation token was cancelled, over the lifetime of the pool.
+    pub fn cancelled_count(&self) -> usize {
+        self.shared_data.cancelled_count.load(Ordering::Relaxed)
     }
 
     /// Returns the number of panicked threads over the lifetime of the pool.
     pub fn panic_count(&self) -> usize {
-        self.panic_count.load(Ordering::Relaxed)
+        self.shared_data.panic_count.load(Ordering::Relaxed)
     }
 
     /// **Deprecated: Use `ThreadPool::set_num_threads`**
@@ -340,92 +702,531 @@ impl ThreadPool {
     /// Will not abort already running or waiting threads.
     pub fn set_num_threads(&mut self, num_threads: usize) {
         assert!(num_threads >= 1);
-        let current_max = (*self.max_count).swap(num_threads, Ordering::Release);
+        let current_max = self.shared_data.max_count.swap(num_threads, Ordering::Release);
+        if num_threads != current_max {
+            trace::resized(&self.shared_data, current_max, num_threads);
+        }
         if num_threads > current_max {
             // Spawn new threads
             for _ in 0..(num_threads - current_max) {
-                self.spawned_count.fetch_add(1, Ordering::SeqCst);
-                spawn_in_pool(self.name.clone(),
-                              self.job_receiver.clone(),
-                              self.active_count.clone(),
-                              self.spawned_count.clone(),
-                              self.min_count.clone(),
-                              self.max_count.clone(),
-                              self.panic_count.clone());
+                self.shared_data.spawned_count.fetch_add(1, Ordering::SeqCst);
+                if spawn_in_pool(self.shared_data.clone()).is_err() {
+                    // The pool will try to grow again when new jobs arrive.
+                    self.shared_data.spawned_count.fetch_sub(1, Ordering::SeqCst);
+                    break;
+                }
             }
         }
     }
+
+    /// Returns how long idle threads of a dynamic pool wait for a job before
+    /// they exit, or `None` if they wait indefinitely.
+    pub fn keep_alive(&self) -> Option<Duration> {
+        self.shared_data.keep_alive()
+    }
+
+    /// Sets how long idle threads of a dynamic pool wait for a job before they
+    /// exit, or lets them wait indefinitely if `keep_alive` is `None`.
+    ///
+    /// With a keep-alive duration, a thread exits once it has found no job to
+    /// run for that long, as long as more than [`min_count`](#method.min_count)
+    /// threads are left. Busy threads keep running, so the pool only shrinks
+    /// once the load has gone down. Without one, a thread exits right after it
+    /// finished a job if no other job is running at that moment, and threads
+    /// that are already waiting for a job stay.
+    ///
+    /// Threads that are already waiting for a job when the duration is changed
+    /// switch to the new one right away, counting the time they have been
+    /// waiting so far. Pools with as many initial threads as maximum
+    /// threads never shrink.
+    ///
+    /// # Panics
+    ///
+    /// This function will panic if `keep_alive` is zero.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use threadpool::ThreadPool;
+    /// use std::thread::sleep;
+    /// use std::time::Duration;
+    ///
+    /// let mut pool = ThreadPool::new_dynamic(4, 1);
+    /// pool.set_keep_alive(Some(Duration::from_millis(10)));
+    /// for _ in 0..4 {
+    ///     pool.execute(|| sleep(Duration::from_millis(20)));
+    /// }
+    /// pool.join();
+    ///
+    /// while pool.spawned_count() > 1 {
+    ///     sleep(Duration::from_millis(10));
+    /// }
+    /// ```
+    pub fn set_keep_alive(&mut self, keep_alive: Option<Duration>) {
+        assert!(keep_alive != Some(Duration::from_secs(0)));
+        let nanos = keep_alive.map_or(0, builder::duration_nanos);
+        self.shared_data.keep_alive_nanos.store(nanos, Ordering::Relaxed);
+        // Let waiting workers pick up the new duration.
+        self.shared_data.job_queue.wake(usize::MAX);
+    }
+
+    /// Limits the number of jobs waiting in the queue to `capacity`, or removes
+    /// the limit if `capacity` is `None`.
+    ///
+    /// Jobs submitted while the queue is full are handled according to the
+    /// [rejection policy](#method.set_rejection_policy), which blocks the
+    /// submitting thread by default. Jobs that are already queued are never
+    /// dropped when the capacity is lowered.
+    ///
+    /// # Panics
+    ///
+    /// This function will panic if `capacity` is `Some(0)`.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use threadpool::{PoolError, RejectionPolicy, ThreadPool};
+    /// use std::sync::mpsc::channel;
+    ///
+    /// let mut pool = ThreadPool::new(1);
+    /// pool.set_queue_capacity(Some(1));
+    /// pool.set_rejection_policy(RejectionPolicy::Abort);
+    ///
+    /// let (started_tx, started_rx) = channel();
+    /// let (release_tx, release_rx) = channel::<()>();
+    /// pool.execute(move || {
+    ///     started_tx.send(()).unwrap();
+    ///     release_rx.recv().unwrap();
+    /// });
+    /// started_rx.recv().unwrap();
+    ///
+    /// // One job fits in the queue, the next one is refused.
+    /// assert!(pool.try_execute(|| {}).is_ok());
+    /// match pool.try_execute(|| {}) {
+    ///     Err(PoolError::QueueFull) => {}
+    ///     _ => panic!("the queue should be full"),
+    /// }
+    /// release_tx.send(()).unwrap();
+    /// ```
+    pub fn set_queue_capacity(&mut self, capacity: Option<usize>) {
+        assert!(capacity != Some(0));
+        self.shared_data.job_queue.set_capacity(capacity);
+    }
+
+    /// Sets what the pool does with new jobs while its queue is full.
+    ///
+    /// With [`RejectionPolicy::Block`], submitting from inside a job of the
+    /// same pool can deadlock if every worker ends up waiting for room in the
+    /// queue.
+    ///
+    /// [`RejectionPolicy::Block`]: enum.RejectionPolicy.html#variant.Block
+    pub fn set_rejection_policy(&mut self, policy: RejectionPolicy) {
+        self.shared_data.job_queue.set_policy(policy);
+    }
+
+    /// Sets the time after which a queued job gains one level of priority.
+    ///
+    /// A job with `Priority::Low` that has been waiting for two intervals is
+    /// treated like a new job with `Priority::High`. The default is 500
+    /// milliseconds.
+    ///
+    /// Pools created with [`with_job_queue`](#method.with_job_queue) leave
+    /// ordering to their queue and ignore the interval.
+    ///
+    /// # Panics
+    ///
+    /// This function will panic if `interval` is zero.
+    pub fn set_aging_interval(&mut self, interval: Duration) {
+        assert!(interval > Duration::from_secs(0));
+        self.shared_data.job_queue.set_aging_interval(interval);
+    }
+
+    /// Blocks the current thread until all jobs in the pool have been executed.
+    ///
+    /// Calling `join` on an empty pool will return immediately. It is safe to
+    /// call `join` from multiple threads and on multiple clones of the pool at
+    /// the same time; all of them return once the pool has run out of work.
+    ///
+    /// Returns `true` if any job panicked since the previous call to `join`.
+    ///
+    /// # Panics
+    ///
+    /// With [`PanicPolicy::Propagate`](enum.PanicPolicy.html#variant.Propagate),
+    /// this function re-raises the panic of a job that has not been re-raised
+    /// yet.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use threadpool::ThreadPool;
+    /// use std::sync::Arc;
+    /// use std::sync::atomic::{AtomicUsize, Ordering};
+    ///
+    /// let pool = ThreadPool::new(4);
+    /// let test_count = Arc::new(AtomicUsize::new(0));
+    ///
+    /// for _ in 0..42 {
+    ///     let test_count = test_count.clone();
+    ///     pool.execute(move || {
+    ///         test_count.fetch_add(1, Ordering::Relaxed);
+    ///     });
+    /// }
+    ///
+    /// assert!(!pool.join());
+    /// assert_eq!(42, test_count.load(Ordering::Relaxed));
+    /// ```
+    pub fn join(&self) -> bool {
+        self.wait_for_no_work(None);
+        let panic_count = self.shared_data.panic_count.load(Ordering::SeqCst);
+        let joined_panic_count = self.shared_data.joined_panic_count.swap(panic_count, Ordering::SeqCst);
+        self.shared_data.panic_log.propagate();
+        panic_count != joined_panic_count
+    }
+
+    /// Blocks the current thread until all jobs in the pool have been executed
+    /// or `timeout` has elapsed.
+    ///
+    /// Returns `true` if the pool ran out of work before the timeout.
+    pub fn join_timeout(&self, timeout: Duration) -> bool {
+        let done = self.wait_for_no_work(Some(Instant::now() + timeout));
+        self.shared_data.panic_log.propagate();
+        done
+    }
+
+    fn wait_for_no_work(&self, deadline: Option<Instant>) -> bool {
+        // fast path requires no mutex
+        if !self.shared_data.has_work() {
+            return true;
+        }
+
+        let generation = self.shared_data.join_generation.load(Ordering::SeqCst);
+        let mut lock = self.shared_data.empty_trigger.lock().unwrap();
+
+        while generation == self.shared_data.join_generation.load(Ordering::Relaxed) &&
+              self.shared_data.has_work() {
+            lock = match deadline {
+                Some(deadline) => {
+                    let now = Instant::now();
+                    if now >= deadline {
+                        return false;
+                    }
+                    self.shared_data.empty_condvar.wait_timeout(lock, deadline - now).unwrap().0
+                }
+                None => self.shared_data.empty_condvar.wait(lock).unwrap(),
+            };
+        }
+
+        // Let every other thread waiting on this generation return as well,
+        // even if new jobs arrive before they get to run.
+        let _ = self.shared_data.join_generation.compare_exchange(generation,
+                                                                  generation.wrapping_add(1),
+                                                                  Ordering::SeqCst,
+                                                                  Ordering::SeqCst);
+        true
+    }
+
+    /// Shuts the pool down gracefully.
+    ///
+    /// The pool stops accepting new jobs, but every job that is already queued
+    /// is still executed. Worker threads exit once the queue is empty. Use
+    /// [`await_termination`](#method.await_termination) to wait for them.
+    ///
+    /// Delayed and periodic jobs that are not due yet are discarded.
+    pub fn shutdown(&self) {
+        self.shared_data.job_queue.close();
+        self.shared_data.scheduler.close();
+        self.shared_data.job_monitor.close();
+        trace::shut_down(&self.shared_data, None);
+        self.shared_data.terminated_notify_all();
+    }
+
+    /// Shuts the pool down immediately.
+    ///
+    /// The pool stops accepting new jobs and all jobs that have not started yet
+    /// are removed from the queue and returned. Jobs that are already running
+    /// are not interrupted; their worker threads exit once they finish.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use threadpool::ThreadPool;
+    /// use std::sync::mpsc::channel;
+    /// use std::time::Duration;
+    ///
+    /// let pool = ThreadPool::new(1);
+    /// let (tx, rx) = channel::<()>();
+    /// pool.execute(move || {
+    ///     rx.recv().unwrap();
+    /// });
+    /// for _ in 0..3 {
+    ///     pool.execute(|| println!("never runs"));
+    /// }
+    ///
+    /// let pending = pool.shutdown_now();
+    /// assert!(pending.len() >= 3);
+    /// tx.send(()).unwrap();
+    /// assert!(pool.await_termination(Duration::from_secs(10)));
+    /// ```
+    pub fn shutdown_now(&self) -> Vec<PendingJob> {
+        self.shared_data.shutdown_now()
+    }
+
+    /// Returns `true` if the pool has been shut down.
+    pub fn is_shutdown(&self) -> bool {
+        self.shared_data.job_queue.is_closed()
+    }
+
+    /// Blocks the current thread until the pool has been shut down and all of
+    /// its worker threads have exited, or `timeout` has elapsed.
+    ///
+    /// Returns `true` if the pool terminated before the timeout.
+    pub fn await_termination(&self, timeout: Duration) -> bool {
+        let deadline = Instant::now() + timeout;
+        let mut lock = self.shared_data.terminated_trigger.lock().unwrap();
+        while !self.shared_data.is_terminated() {
+            let now = Instant::now();
+            if now >= deadline {
+                return false;
+            }
+            lock = self.shared_data.terminated_condvar.wait_timeout(lock, deadline - now).unwrap().0;
+        }
+        drop(lock);
+        self.shared_data.panic_log.propagate();
+        true
+    }
 }
 
-fn spawn_in_pool(name: Option<String>,
-                 jobs: Arc<Mutex<Receiver<Thunk<'static>>>>,
-                 thread_counter: Arc<AtomicUsize>,
-                 thread_count_spawned: Arc<AtomicUsize>,
-                 thread_count_min: Arc<AtomicUsize>,
-                 thread_count_max: Arc<AtomicUsize>,
-                 thread_count_panic: Arc<AtomicUsize>) {
+/// Spawns up to `count` new threads, as long as the number of spawned threads
+/// is smaller than the maximum allowed.
+fn grow_pool(shared_data: &Arc<ThreadPoolSharedData>, count: usize) -> Result<(), PoolError> {
+    for _ in 0..count {
+        if shared_data.spawned_count.load(Ordering::Acquire) >= shared_data.thread_limit() {
+            break;
+        }
+        if shared_data.job_queue.is_closed() {
+            return Err(PoolError::ShutDown);
+        }
+        shared_data.spawned_count.fetch_add(1, Ordering::SeqCst);
+        if let Err(err) = spawn_in_pool(shared_data.clone()) {
+            // Keep going with the threads we have, if there are any.
+            if shared_data.spawned_count.fetch_sub(1, Ordering::SeqCst) == 1 {
+                return Err(PoolError::SpawnFailed(err));
+            }
+            break;
+        }
+    }
+    Ok(())
+}
+
+/// Adds a job to the pool. Unless `blocking` is `true`, the job is refused
+/// instead of waiting for room in a full queue or running on the calling
+/// thread.
+fn execute_in_pool(shared_data: &Arc<ThreadPoolSharedData>,
+                   job: Job,
+                   priority: Priority,
+                   blocking: bool)
+                   -> Result<(), PoolError> {
+    grow_pool(shared_data, 1)?;
+    shared_data.add_submitted(1);
+    let job = match WorkerQueues::push_local(shared_data, job, priority) {
+        Ok(()) => return Ok(()),
+        Err(job) => job,
+    };
+    // An evicted job takes its place in the queued count with it, even if the
+    // push fails anyway.
+    match shared_data.job_queue.push(job, priority, blocking) {
+        Ok(None) => Ok(()),
+        Ok(Some(evicted)) => {
+            shared_data.queued_count.fetch_sub(1, Ordering::SeqCst);
+            drop(evicted);
+            Ok(())
+        }
+        Err(PushError::Closed(job, evicted)) => {
+            shared_data.remove_submitted(1);
+            shared_data.queued_count.fetch_sub(evicted.is_some() as usize, Ordering::SeqCst);
+            drop((job, evicted));
+            Err(PoolError::ShutDown)
+        }
+        Err(PushError::Full(job, evicted)) => {
+            shared_data.remove_submitted(1);
+            shared_data.queued_count.fetch_sub(evicted.is_some() as usize, Ordering::SeqCst);
+            drop(evicted);
+            match shared_data.job_queue.policy() {
+                RejectionPolicy::CallerRuns if blocking => {
+                    if !job.is_cancelled() {
+                        job.run();
+                    }
+                    Ok(())
+                }
+                RejectionPolicy::DiscardNewest => Ok(()),
+                _ => Err(PoolError::QueueFull),
+            }
+        }
+    }
+}
+
+/// Adds a job to the pool if there is room for it right away, and returns it
+/// otherwise, whatever the rejection policy.
+fn offer_to_pool(shared_data: &Arc<ThreadPoolSharedData>,
+                 job: Job,
+                 priority: Priority)
+                 -> Result<Option<Job>, PoolError> {
+    grow_pool(shared_data, 1)?;
+    shared_data.add_submitted(1);
+    let job = match WorkerQueues::push_local(shared_data, job, priority) {
+        Ok(()) => return Ok(None),
+        Err(job) => job,
+    };
+    match shared_data.job_queue.try_push(job, priority) {
+        Ok(()) => Ok(None),
+        Err(PushError::Closed(job, _)) => {
+            shared_data.remove_submitted(1);
+            drop(job);
+            Err(PoolError::ShutDown)
+        }
+        Err(PushError::Full(job, _)) => {
+            shared_data.remove_submitted(1);
+            Ok(Some(job))
+        }
+    }
+}
+
+/// Runs a job taken from the queue on the worker with index `worker`, or
+/// drops it if it has been cancelled.
+fn run_job(shared_data: &ThreadPoolSharedData, worker: Option<usize>, job: Job) {
+    shared_data.queued_count.fetch_sub(1, Ordering::SeqCst);
+    if job.is_cancelled() {
+        drop(job);
+        shared_data.cancelled_count.fetch_add(1, Ordering::SeqCst);
+    } else {
+        let _watch = JobMonitor::watch(shared_data, &job);
+        let _hooks = shared_data.hooks.around_job(worker);
+        let _span = trace::job_span(shared_data, worker, job.name());
+        #[cfg(feature = "prometheus")]
+        let _timer = shared_data.job_durations.time();
+        panics::run_reporting_panics(shared_data, worker, job);
+        shared_data.completed_count.fetch_add(1, Ordering::SeqCst);
+    }
+}
+
+fn spawn_in_pool(shared_data: Arc<ThreadPoolSharedData>) -> io::Result<()> {
+    let index = shared_data.worker_indices.acquire();
+    let result = spawn_worker(shared_data.clone(), index);
+    if let Err(ref error) = result {
+        trace::spawn_failed(&shared_data, index, error);
+        shared_data.worker_indices.release(index);
+    }
+    result
+}
+
+/// Spawns the worker thread with `index`, which must have been acquired.
+fn spawn_worker(shared_data: Arc<ThreadPoolSharedData>, index: usize) -> io::Result<()> {
     let mut builder = Builder::new();
-    if let Some(ref name) = name {
-        builder = builder.name(name.clone());
+    if let Some(ref name) = shared_data.name {
+        builder = builder.name(index::thread_name(name, index));
+    }
+    if let Some(stack_size) = shared_data.stack_size {
+        builder = builder.stack_size(stack_size);
     }
     builder.spawn(move || {
+            shared_data.threads_spawned.fetch_add(1, Ordering::SeqCst);
+            // Dropped after the sentinel, so the thread is still known as
+            // the worker with `index` while it stops.
+            let worker = Worker::register(&shared_data, index);
+            trace::worker_started(&shared_data, index);
+            shared_data.hooks.thread_start(index);
 
             // Will spawn a new thread on panic unless it is cancelled.
-            let sentinel = Sentinel::new(name,
-                                         &jobs,
-                                         &thread_counter,
-                                         &thread_count_spawned,
-                                         &thread_count_min,
-                                         &thread_count_max,
-                                         &thread_count_panic);
+            let sentinel = Sentinel::new(&shared_data, index);
+            // Set once this thread has given up its place in `spawned_count`.
+            let mut retired = false;
 
             loop {
                 // Shutdown this thread if the pool has become smaller
-                let thread_counter_val = thread_counter.load(Ordering::Acquire);
-                let thread_count_min_val = thread_count_min.load(Ordering::Relaxed);
-                let thread_count_max_val = thread_count_max.load(Ordering::Relaxed);
-                if thread_counter_val < thread_count_max_val {
-                    let message = {
-                        // Only lock jobs for the time it takes
-                        // to get a job, not run it.
-                        let lock = jobs.lock().unwrap();
-                        lock.recv()
+                let thread_counter_val = shared_data.active_count.load(Ordering::Acquire);
+                let thread_count_min_val = shared_data.min_count.load(Ordering::Relaxed);
+                let thread_count_max_val = shared_data.max_count.load(Ordering::Relaxed);
+                if thread_counter_val < shared_data.thread_limit() {
+                    // Only workers of dynamic pools give up waiting for jobs.
+                    let keep_alive = || if thread_count_min_val != thread_count_max_val {
+                        shared_data.keep_alive()
+                    } else {
+                        None
                     };
-
-                    match message {
-                        Ok(job) => {
+                    match worker.next_job(keep_alive) {
+                        Some(job) => {
                             // Do not allow IR around the job execution
-                            thread_counter.fetch_add(1, Ordering::SeqCst);
-                            job.call_box();
-                            thread_counter.fetch_sub(1, Ordering::SeqCst);
-                            // Shutdown this thread if there are no active jobs and number of
-                            // spawned threads more than the minimum.
-                            if thread_count_min_val != thread_count_max_val &&
-                               thread_counter.load(Ordering::Acquire) == 0 &&
-                               thread_count_spawned.load(Ordering::Acquire) > thread_count_min_val {
+                            let active = shared_data.active_count.fetch_add(1, Ordering::SeqCst) + 1;
+                            shared_data.peak_active.fetch_max(active, Ordering::SeqCst);
+                            if shared_data.panic_log.catches_panics() {
+                                let result = panic::catch_unwind(AssertUnwindSafe(|| run_job(&shared_data, Some(index), job)));
+                                if result.is_err() {
+                                    shared_data.panic_count.fetch_add(1, Ordering::SeqCst);
+                                }
+                            } else {
+                                run_job(&shared_data, Some(index), job);
+                            }
+                            shared_data.active_count.fetch_sub(1, Ordering::SeqCst);
+                            shared_data.no_work_notify_all();
+                            // Once the overdue job a worker was spawned to
+                            // compensate for has finished, the pool has one
+                            // thread too many.
+                            if !worker.has_local_jobs() && shared_data.try_retire(shared_data.thread_limit()) {
+                                retired = true;
+                                break;
+                            }
+                            // Without a keep-alive duration, shutdown this thread if there
+                            // are no active jobs and number of spawned threads more than
+                            // the minimum.
+                            if keep_alive().is_none() &&
+                               thread_count_min_val != thread_count_max_val &&
+                               shared_data.active_count.load(Ordering::Acquire) == 0 &&
+                               !worker.has_local_jobs() &&
+                               shared_data.try_retire(thread_count_min_val) {
+                                retired = true;
                                 break;
                             }
                         }
 
-                        // The ThreadPool was dropped.
-                        Err(..) => break,
+                        // The ThreadPool was shut down or dropped.
+                        None if shared_data.job_queue.is_closed() => break,
+
+                        // Shutdown this thread if it has been idle for the
+                        // keep-alive duration and there are more than the
+                        // minimum number of threads.
+                        None => {
+                            if !worker.has_local_jobs() && shared_data.try_retire(thread_count_min_val) {
+                                retired = true;
+                                break;
+                            }
+                        }
                     }
                 } else {
                     break;
                 }
             }
 
-            thread_count_spawned.fetch_sub(1, Ordering::SeqCst);
+            trace::worker_stopped(&shared_data, index);
+            shared_data.hooks.thread_stop(index);
+            drop(worker);
+            shared_data.worker_indices.release(index);
+            shared_data.threads_retired.fetch_add(1, Ordering::SeqCst);
+            if !retired {
+                shared_data.spawned_count.fetch_sub(1, Ordering::SeqCst);
+            }
+            shared_data.terminated_notify_all();
             sentinel.cancel();
         })
-        .unwrap();
+        .map(|_| ())
 }
 
 #[cfg(test)]
+#[allow(unused_must_use, clippy::no_effect, clippy::unnecessary_fold)]
 mod test {
-    use super::ThreadPool;
+    use super::{testing, PoolError, RejectionPolicy, ThreadPool, ThreadPoolBuilder};
     use std::sync::mpsc::{sync_channel, channel};
     use std::sync::{Arc, Barrier};
+    use std::sync::atomic::{AtomicUsize, Ordering};
     use std::thread::{self, sleep};
     use std::time::Duration;
 
@@ -590,9 +1391,10 @@ mod test {
         b1.wait();
 
         assert_eq!(rx.iter().take(test_tasks).fold(0, |a, b| a + b), test_tasks);
-        // `iter().take(test_tasks).fold` may be faster than the last thread finishing itself, so
-        // values of 0 or 1 are ok.
-        assert!(pool.active_count() <= 1);
+        // `iter().take(test_tasks).fold` may be faster than the last threads finishing
+        // themselves, so wait for them.
+        pool.join();
+        assert_eq!(pool.active_count(), 0);
     }
 
     #[test]
@@ -725,4 +1527,293 @@ mod test {
             assert_eq!(name, thread_name);
         }
     }
+
+    #[test]
+    fn test_join() {
+        let pool = ThreadPool::new(TEST_TASKS);
+        let counter = Arc::new(AtomicUsize::new(0));
+
+        for _ in 0..(TEST_TASKS * 10) {
+            let counter = counter.clone();
+            pool.execute(move || {
+                sleep(Duration::from_millis(5));
+                counter.fetch_add(1, Ordering::SeqCst);
+            });
+        }
+
+        assert!(!pool.join());
+        assert_eq!(counter.load(Ordering::SeqCst), TEST_TASKS * 10);
+        assert_eq!(pool.active_count(), 0);
+
+        // Joining an idle pool returns immediately.
+        assert!(!pool.join());
+    }
+
+    #[test]
+    fn test_join_reports_panics() {
+        let pool = ThreadPool::new(TEST_TASKS);
+        for _ in 0..TEST_TASKS {
+            pool.execute(move || -> () { panic!("Ignore this panic, it should!") });
+        }
+        assert!(pool.join());
+        assert_eq!(pool.panic_count(), TEST_TASKS);
+
+        pool.execute(move || {});
+        assert!(!pool.join());
+    }
+
+    #[test]
+    fn test_join_timeout() {
+        let pool = ThreadPool::new(TEST_TASKS);
+        let (tx, rx) = channel::<()>();
+        pool.execute(move || {
+            rx.recv().unwrap();
+        });
+
+        assert!(!pool.join_timeout(Duration::from_millis(50)));
+        tx.send(()).unwrap();
+        assert!(pool.join_timeout(Duration::from_secs(10)));
+    }
+
+    #[test]
+    fn test_join_from_many_threads() {
+        let pool = ThreadPool::new(TEST_TASKS);
+        let counter = Arc::new(AtomicUsize::new(0));
+
+        for _ in 0..(TEST_TASKS * 4) {
+            let counter = counter.clone();
+            pool.execute(move || {
+                sleep(Duration::from_millis(10));
+                counter.fetch_add(1, Ordering::SeqCst);
+            });
+        }
+
+        let joiners: Vec<_> = (0..TEST_TASKS)
+            .map(|_| {
+                let pool = pool.clone();
+                let counter = counter.clone();
+                thread::spawn(move || {
+                    pool.join();
+                    counter.load(Ordering::SeqCst)
+                })
+            })
+            .collect();
+
+        for joiner in joiners {
+            assert_eq!(joiner.join().unwrap(), TEST_TASKS * 4);
+        }
+    }
+
+    #[test]
+    fn test_shutdown_finishes_queue() {
+        let pool = ThreadPool::new(2);
+        let counter = Arc::new(AtomicUsize::new(0));
+
+        for _ in 0..20 {
+            let counter = counter.clone();
+            pool.execute(move || {
+                sleep(Duration::from_millis(5));
+                counter.fetch_add(1, Ordering::SeqCst);
+            });
+        }
+
+        assert!(!pool.is_shutdown());
+        pool.shutdown();
+        assert!(pool.is_shutdown());
+        match pool.try_execute(|| {}) {
+            Err(PoolError::ShutDown) => {}
+            other => panic!("unexpected result: {:?}", other),
+        }
+
+        assert!(pool.await_termination(Duration::from_secs(10)));
+        assert_eq!(counter.load(Ordering::SeqCst), 20);
+        assert_eq!(pool.spawned_count(), 0);
+    }
+
+    #[test]
+    fn test_shutdown_now_returns_pending_jobs() {
+        let (pool, release) = testing::blocked_pool();
+        let counter = Arc::new(AtomicUsize::new(0));
+
+        for _ in 0..5 {
+            let counter = counter.clone();
+            pool.execute(move || {
+                counter.fetch_add(1, Ordering::SeqCst);
+            });
+        }
+
+        let pending = pool.shutdown_now();
+        assert_eq!(pending.len(), 5);
+        assert!(pool.try_execute(|| {}).is_err());
+
+        // The running job keeps the pool alive until it finishes.
+        assert!(!pool.await_termination(Duration::from_millis(50)));
+        release.send(()).unwrap();
+        assert!(pool.await_termination(Duration::from_secs(10)));
+        assert_eq!(counter.load(Ordering::SeqCst), 0);
+
+        for job in pending {
+            job.run();
+        }
+        assert_eq!(counter.load(Ordering::SeqCst), 5);
+    }
+
+    #[test]
+    fn test_await_termination_without_shutdown() {
+        let pool = ThreadPool::new(TEST_TASKS);
+        assert!(!pool.await_termination(Duration::from_millis(50)));
+        assert_eq!(pool.spawned_count(), TEST_TASKS);
+    }
+
+    #[test]
+    fn test_keep_alive_retires_idle_threads() {
+        let pool = ThreadPoolBuilder::new()
+            .num_threads(4)
+            .initial_threads(1)
+            .keep_alive(Duration::from_millis(50))
+            .build()
+            .unwrap();
+        let (tx, rx) = channel::<()>();
+        for _ in 0..4 {
+            let tx = tx.clone();
+            pool.execute(move || {
+                sleep(Duration::from_millis(100));
+                drop(tx);
+            });
+        }
+        drop(tx);
+        assert!(rx.recv().is_err());
+
+        for _ in 0..100 {
+            if pool.spawned_count() == 1 {
+                break;
+            }
+            sleep(Duration::from_millis(10));
+        }
+        assert_eq!(pool.spawned_count(), 1);
+    }
+
+    #[test]
+    fn test_set_keep_alive_applies_to_waiting_threads() {
+        let mut pool = ThreadPoolBuilder::new()
+            .num_threads(4)
+            .initial_threads(1)
+            .keep_alive(Duration::from_secs(3600))
+            .build()
+            .unwrap();
+        let barrier = Arc::new(Barrier::new(5));
+        for _ in 0..4 {
+            let barrier = barrier.clone();
+            pool.execute(move || {
+                barrier.wait();
+            });
+        }
+        barrier.wait();
+        pool.join();
+        assert_eq!(pool.spawned_count(), 4);
+
+        pool.set_keep_alive(Some(Duration::from_millis(10)));
+        for _ in 0..100 {
+            if pool.spawned_count() == 1 {
+                break;
+            }
+            sleep(Duration::from_millis(10));
+        }
+        assert_eq!(pool.spawned_count(), 1);
+    }
+
+    #[test]
+    fn test_keep_alive_stops_at_min_count() {
+        let mut pool = ThreadPool::new_dynamic(4, 2);
+        pool.set_keep_alive(Some(Duration::from_millis(20)));
+        assert_eq!(pool.keep_alive(), Some(Duration::from_millis(20)));
+        assert_eq!(pool.spawned_count(), 2);
+        let barrier = Arc::new(Barrier::new(5));
+        for _ in 0..4 {
+            let barrier = barrier.clone();
+            pool.execute(move || {
+                barrier.wait();
+            });
+        }
+        barrier.wait();
+        pool.join();
+
+        for _ in 0..100 {
+            if pool.spawned_count() == 2 {
+                break;
+            }
+            sleep(Duration::from_millis(10));
+        }
+        // Idle threads keep retiring only down to the minimum.
+        sleep(Duration::from_millis(100));
+        assert_eq!(pool.spawned_count(), 2);
+
+        pool.set_keep_alive(None);
+        assert_eq!(pool.keep_alive(), None);
+    }
+
+    #[test]
+    fn test_invalid_thread_counts() {
+        match ThreadPool::try_new(0) {
+            Err(PoolError::InvalidThreadCount) => {}
+            other => panic!("unexpected result: {:?}", other.map(|_| ())),
+        }
+        assert!(ThreadPoolBuilder::new().num_threads(2).initial_threads(0).build().is_err());
+        assert!(ThreadPoolBuilder::new().num_threads(2).initial_threads(3).build().is_err());
+        assert!(ThreadPoolBuilder::new().num_threads(3).initial_threads(2).build().is_ok());
+    }
+
+    #[test]
+    fn test_try_execute() {
+        let pool = ThreadPool::try_new(TEST_TASKS).unwrap();
+        let (tx, rx) = channel();
+        for _ in 0..TEST_TASKS {
+            let tx = tx.clone();
+            pool.try_execute(move || {
+                    tx.send(1).unwrap();
+                })
+                .unwrap();
+        }
+        assert_eq!(rx.iter().take(TEST_TASKS).sum::<usize>(), TEST_TASKS);
+
+        pool.shutdown();
+        let err = pool.try_execute(|| {}).unwrap_err();
+        assert_eq!(err.to_string(), "thread pool has been shut down");
+    }
+
+    #[test]
+    fn test_execute_batch() {
+        let pool = ThreadPool::new(TEST_TASKS);
+        let (tx, rx) = channel();
+        let jobs = (0..100).map(|i| {
+            let tx = tx.clone();
+            move || tx.send(i).unwrap()
+        });
+        assert_eq!(pool.execute_batch(jobs), 100);
+        assert_eq!(rx.iter().take(100).sum::<usize>(), 4950);
+
+        assert_eq!(pool.execute_batch(Vec::<fn()>::new()), 0);
+        pool.shutdown();
+        assert_eq!(pool.execute_batch(vec![|| {}]), 0);
+    }
+
+    #[test]
+    fn test_execute_batch_bounded() {
+        let mut pool = ThreadPool::new(1);
+        pool.set_queue_capacity(Some(2));
+        pool.set_rejection_policy(RejectionPolicy::Abort);
+        let release = testing::block(&pool);
+
+        let ran = Arc::new(AtomicUsize::new(0));
+        let jobs = (0..5).map(|_| {
+            let ran = ran.clone();
+            move || {
+                ran.fetch_add(1, Ordering::SeqCst);
+            }
+        });
+        assert_eq!(pool.execute_batch(jobs), 2);
+        release.send(()).unwrap();
+        pool.join();
+        assert_eq!(ran.load(Ordering::SeqCst), 2);
+    }
 }
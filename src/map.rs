@@ -0,0 +1,375 @@
+// Copyright 2014 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Mapping a function over an iterator on the threads of a pool.
+
+use std::collections::BTreeMap;
+use std::panic;
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::Arc;
+use std::thread;
+
+use super::{handle, CancellationToken, JobBuilder, ThreadPool};
+
+type MapFn<A, T> = Arc<dyn Fn(A) -> T + Send + Sync>;
+type Reply<T> = (usize, thread::Result<T>);
+
+/// Delivers the result for one input, or reports the job as lost if it is
+/// dropped before it ran.
+struct Replier<T> {
+    index: usize,
+    tx: Option<Sender<Reply<T>>>,
+}
+
+impl<T> Replier<T> {
+    fn send(mut self, result: thread::Result<T>) {
+        let _ = self.tx.take().unwrap().send((self.index, result));
+    }
+}
+
+impl<T> Drop for Replier<T> {
+    fn drop(&mut self) {
+        if let Some(tx) = self.tx.take() {
+            let _ = tx.send((self.index, Err(handle::lost())));
+        }
+    }
+}
+
+/// The inputs of a map and the jobs submitted for them.
+struct Inputs<'pool, I: Iterator, T> {
+    pool: &'pool ThreadPool,
+    iter: I,
+    f: MapFn<I::Item, T>,
+    token: CancellationToken,
+    tx: Sender<Reply<T>>,
+    rx: Receiver<Reply<T>>,
+    submitted: usize,
+    // Inputs that were submitted but whose result has not been yielded yet.
+    in_flight: usize,
+    max_in_flight: usize,
+}
+
+impl<'pool, I, T> Inputs<'pool, I, T>
+    where I: Iterator,
+          I::Item: Send + 'static,
+          T: Send + 'static
+{
+    fn new(pool: &'pool ThreadPool, iter: I, f: MapFn<I::Item, T>) -> Inputs<'pool, I, T> {
+        let (tx, rx) = channel();
+        Inputs {
+            pool,
+            iter,
+            f,
+            token: CancellationToken::new(),
+            tx,
+            rx,
+            submitted: 0,
+            in_flight: 0,
+            max_in_flight: 2 * pool.max_count(),
+        }
+    }
+
+    /// Submits inputs until `max_in_flight` of them are in flight.
+    fn fill(&mut self) {
+        while self.in_flight < self.max_in_flight {
+            let item = match self.iter.next() {
+                Some(item) => item,
+                None => return,
+            };
+            let f = self.f.clone();
+            let replier = Replier {
+                index: self.submitted,
+                tx: Some(self.tx.clone()),
+            };
+            JobBuilder::new().cancellation_token(self.token.clone()).execute(self.pool, move || {
+                handle::run_catching(|| f(item), |result| replier.send(result));
+            });
+            self.submitted += 1;
+            self.in_flight += 1;
+        }
+    }
+
+    fn recv(&mut self) -> Reply<T> {
+        // `self.tx` is never dropped before `self.rx`, so this cannot fail.
+        self.rx.recv().unwrap()
+    }
+
+    fn yielded(&mut self, result: thread::Result<T>) -> T {
+        self.in_flight -= 1;
+        match result {
+            Ok(value) => value,
+            Err(payload) => panic::resume_unwind(payload),
+        }
+    }
+}
+
+impl<'pool, I: Iterator, T> Drop for Inputs<'pool, I, T> {
+    fn drop(&mut self) {
+        // Jobs that have not started yet are not needed anymore.
+        self.token.cancel();
+    }
+}
+
+/// An iterator over the results of [`ThreadPool::map`], in input order.
+///
+/// Dropping the iterator cancels the jobs that have not started yet.
+///
+/// [`ThreadPool::map`]: struct.ThreadPool.html#method.map
+pub struct Map<'pool, I: Iterator, T> {
+    inputs: Inputs<'pool, I, T>,
+    // Results that arrived before the results of earlier inputs.
+    early: BTreeMap<usize, thread::Result<T>>,
+    next_index: usize,
+}
+
+impl<'pool, I: Iterator, T> Map<'pool, I, T> {
+    /// Sets the maximum number of inputs that are submitted to the pool but
+    /// whose results have not been yielded yet. Defaults to twice the maximum
+    /// number of threads of the pool.
+    ///
+    /// # Panics
+    ///
+    /// This function will panic if `max_in_flight` is 0.
+    pub fn max_in_flight(mut self, max_in_flight: usize) -> Map<'pool, I, T> {
+        assert!(max_in_flight > 0);
+        self.inputs.max_in_flight = max_in_flight;
+        self
+    }
+}
+
+impl<'pool, I, T> Iterator for Map<'pool, I, T>
+    where I: Iterator,
+          I::Item: Send + 'static,
+          T: Send + 'static
+{
+    type Item = T;
+
+    fn next(&mut self) -> Option<T> {
+        self.inputs.fill();
+        loop {
+            if let Some(result) = self.early.remove(&self.next_index) {
+                self.next_index += 1;
+                return Some(self.inputs.yielded(result));
+            }
+            if self.inputs.in_flight == 0 {
+                return None;
+            }
+            let (index, result) = self.inputs.recv();
+            self.early.insert(index, result);
+        }
+    }
+}
+
+/// An iterator over the results of [`ThreadPool::map_unordered`], in the order
+/// in which the jobs finished.
+///
+/// Dropping the iterator cancels the jobs that have not started yet.
+///
+/// [`ThreadPool::map_unordered`]: struct.ThreadPool.html#method.map_unordered
+pub struct MapUnordered<'pool, I: Iterator, T> {
+    inputs: Inputs<'pool, I, T>,
+}
+
+impl<'pool, I: Iterator, T> MapUnordered<'pool, I, T> {
+    /// Sets the maximum number of inputs that are submitted to the pool but
+    /// whose results have not been yielded yet. Defaults to twice the maximum
+    /// number of threads of the pool.
+    ///
+    /// # Panics
+    ///
+    /// This function will panic if `max_in_flight` is 0.
+    pub fn max_in_flight(mut self, max_in_flight: usize) -> MapUnordered<'pool, I, T> {
+        assert!(max_in_flight > 0);
+        self.inputs.max_in_flight = max_in_flight;
+        self
+    }
+}
+
+impl<'pool, I, T> Iterator for MapUnordered<'pool, I, T>
+    where I: Iterator,
+          I::Item: Send + 'static,
+          T: Send + 'static
+{
+    type Item = T;
+
+    fn next(&mut self) -> Option<T> {
+        self.inputs.fill();
+        if self.inputs.in_flight == 0 {
+            return None;
+        }
+        let (_, result) = self.inputs.recv();
+        Some(self.inputs.yielded(result))
+    }
+}
+
+impl ThreadPool {
+    /// Applies `f` to every item of `inputs` on the threads of the pool and
+    /// returns an iterator over the results, in the order of the inputs.
+    ///
+    /// Inputs are taken from `inputs` lazily, as the results are consumed, so
+    /// that at most [`max_in_flight`] of them are in the pool at any time. If
+    /// `f` panics for an input, the panic is propagated when the iterator
+    /// reaches the result of that input.
+    ///
+    /// # Panics
+    ///
+    /// Advancing the iterator will panic if the pool has been shut down.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use threadpool::ThreadPool;
+    ///
+    /// let pool = ThreadPool::new(4);
+    /// let squares: Vec<u64> = pool.map(1..6, |x: u64| x * x).max_in_flight(8).collect();
+    /// assert_eq!(squares, vec![1, 4, 9, 16, 25]);
+    /// ```
+    ///
+    /// [`max_in_flight`]: struct.Map.html#method.max_in_flight
+    pub fn map<I, F, T>(&self, inputs: I, f: F) -> Map<'_, I::IntoIter, T>
+        where I: IntoIterator,
+              I::Item: Send + 'static,
+              F: Fn(I::Item) -> T + Send + Sync + 'static,
+              T: Send + 'static
+    {
+        Map {
+            inputs: Inputs::new(self, inputs.into_iter(), Arc::new(f)),
+            early: BTreeMap::new(),
+            next_index: 0,
+        }
+    }
+
+    /// Applies `f` to every item of `inputs` on the threads of the pool and
+    /// returns an iterator over the results, in the order in which they are
+    /// computed.
+    ///
+    /// Like [`map`](#method.map), but results do not wait for the results of
+    /// earlier inputs.
+    ///
+    /// # Panics
+    ///
+    /// Advancing the iterator will panic if the pool has been shut down.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use threadpool::ThreadPool;
+    ///
+    /// let pool = ThreadPool::new(4);
+    /// let mut lengths: Vec<usize> = pool.map_unordered(vec!["a", "bb", "ccc"], |s: &str| s.len())
+    ///     .collect();
+    /// lengths.sort();
+    /// assert_eq!(lengths, vec![1, 2, 3]);
+    /// ```
+    pub fn map_unordered<I, F, T>(&self, inputs: I, f: F) -> MapUnordered<'_, I::IntoIter, T>
+        where I: IntoIterator,
+              I::Item: Send + 'static,
+              F: Fn(I::Item) -> T + Send + Sync + 'static,
+              T: Send + 'static
+    {
+        MapUnordered { inputs: Inputs::new(self, inputs.into_iter(), Arc::new(f)) }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::ThreadPool;
+    use std::panic::{self, AssertUnwindSafe};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::thread::sleep;
+    use std::time::Duration;
+
+    #[test]
+    fn test_map_keeps_input_order() {
+        let pool = ThreadPool::new(4);
+        // Later inputs finish first.
+        let results: Vec<u64> = pool.map(0..20, |i: u64| {
+                sleep(Duration::from_millis(20 - i));
+                i * 10
+            })
+            .collect();
+        assert_eq!(results, (0..20).map(|i| i * 10).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_map_unordered_yields_all() {
+        let pool = ThreadPool::new(4);
+        let mut results: Vec<usize> = pool.map_unordered(0..100, |i: usize| i + 1).collect();
+        results.sort();
+        assert_eq!(results, (1..101).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_map_bounds_in_flight() {
+        let pool = ThreadPool::new(4);
+        let pulled = Arc::new(AtomicUsize::new(0));
+        let inputs = {
+            let pulled = pulled.clone();
+            (0..50).inspect(move |_| {
+                pulled.fetch_add(1, Ordering::SeqCst);
+            })
+        };
+
+        let mut consumed = 0;
+        for _ in pool.map(inputs, |i: usize| i).max_in_flight(3) {
+            consumed += 1;
+            assert!(pulled.load(Ordering::SeqCst) - consumed < 3);
+        }
+        assert_eq!(consumed, 50);
+
+        consumed = 0;
+        pulled.store(0, Ordering::SeqCst);
+        let inputs = {
+            let pulled = pulled.clone();
+            (0..50).inspect(move |_| {
+                pulled.fetch_add(1, Ordering::SeqCst);
+            })
+        };
+        for _ in pool.map_unordered(inputs, |i: usize| i).max_in_flight(5) {
+            consumed += 1;
+            assert!(pulled.load(Ordering::SeqCst) - consumed < 5);
+        }
+        assert_eq!(consumed, 50);
+    }
+
+    #[test]
+    fn test_map_propagates_panic() {
+        let pool = ThreadPool::new(2);
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            pool.map(0..10, |i: usize| if i == 4 {
+                    panic!("map boom")
+                } else {
+                    i
+                })
+                .collect::<Vec<_>>()
+        }));
+        let payload = result.unwrap_err();
+        assert_eq!(payload.downcast_ref::<&str>(), Some(&"map boom"));
+        pool.join();
+        assert_eq!(pool.panic_count(), 1);
+    }
+
+    #[test]
+    fn test_map_drop_cancels_pending_inputs() {
+        let pool = ThreadPool::new(1);
+        {
+            let mut results = pool.map(0..10, |i: usize| {
+                    sleep(Duration::from_millis(10));
+                    i
+                })
+                .max_in_flight(10);
+            assert_eq!(results.next(), Some(0));
+        }
+        pool.join();
+        assert!(pool.cancelled_count() > 0);
+        assert_eq!(pool.completed_count() + pool.cancelled_count(), 10);
+    }
+}
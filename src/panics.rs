@@ -0,0 +1,543 @@
+// Copyright 2014 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Reporting of panicking jobs.
+//!
+//! Workers catch the panic of a job just long enough to describe it in a
+//! [`PanicInfo`], which is passed to the handler of the pool and kept in a
+//! short history, and then resume unwinding.
+//!
+//! Backtraces and silencing the default panic output need a process-wide
+//! panic hook. It is installed the first time a pool asks for either, and
+//! only acts on panics of jobs running in such a pool; all other panics are
+//! passed on to the previously installed hook.
+//!
+//! What happens to the pool after a job panicked is chosen with a
+//! [`PanicPolicy`].
+
+use std::any::Any;
+use std::backtrace::Backtrace;
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, Once};
+use std::process;
+use std::thread;
+
+use super::{handle, trace, Job, ThreadPool, ThreadPoolSharedData};
+
+/// The default number of panics a pool remembers.
+const DEFAULT_PANIC_HISTORY: usize = 16;
+
+/// A description of a job that panicked.
+///
+/// Passed to the handler installed with [`ThreadPool::on_panic`] and returned
+/// by [`ThreadPool::recent_panics`].
+///
+/// [`ThreadPool::on_panic`]: struct.ThreadPool.html#method.on_panic
+/// [`ThreadPool::recent_panics`]: struct.ThreadPool.html#method.recent_panics
+#[derive(Clone, Debug)]
+pub struct PanicInfo {
+    message: Option<String>,
+    thread_name: Option<String>,
+    job_name: Option<String>,
+    backtrace: Option<String>,
+}
+
+impl PanicInfo {
+    /// Returns the panic message, if the payload was a string.
+    pub fn message(&self) -> Option<&str> {
+        self.message.as_ref().map(|message| &message[..])
+    }
+
+    /// Returns the name of the worker thread the job ran on, if it has one.
+    pub fn thread_name(&self) -> Option<&str> {
+        self.thread_name.as_ref().map(|name| &name[..])
+    }
+
+    /// Returns the name the job was submitted with, if any.
+    pub fn job_name(&self) -> Option<&str> {
+        self.job_name.as_ref().map(|name| &name[..])
+    }
+
+    /// Returns the backtrace of the panic, if the pool
+    /// [captures backtraces](struct.ThreadPool.html#method.set_panic_backtraces).
+    pub fn backtrace(&self) -> Option<&str> {
+        self.backtrace.as_ref().map(|backtrace| &backtrace[..])
+    }
+}
+
+/// What a pool does after one of its jobs panicked.
+///
+/// The policy applies to every panicking job, including jobs whose panic is
+/// also reported through a [`JobHandle`], a scope or `join2`. It is set with
+/// [`ThreadPool::set_panic_policy`].
+///
+/// [`JobHandle`]: struct.JobHandle.html
+/// [`ThreadPool::set_panic_policy`]: struct.ThreadPool.html#method.set_panic_policy
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum PanicPolicy {
+    /// The worker thread of the job exits and is replaced by a new one. This
+    /// is the default.
+    #[default]
+    Respawn,
+    /// The pool is shut down as if by
+    /// [`shutdown_now`](struct.ThreadPool.html#method.shutdown_now). Jobs that
+    /// have not started yet are dropped and the worker is not replaced.
+    Shutdown,
+    /// The process is aborted, after the panic has been reported.
+    Abort,
+    /// Like `Respawn`, but the panic is also re-raised in the next caller of
+    /// [`join`](struct.ThreadPool.html#method.join),
+    /// [`join_timeout`](struct.ThreadPool.html#method.join_timeout) or
+    /// [`await_termination`](struct.ThreadPool.html#method.await_termination)
+    /// on the pool. If several jobs panic before that, the first panic is
+    /// re-raised.
+    Propagate,
+}
+
+type PanicHandler = Arc<dyn Fn(PanicInfo) + Send + Sync>;
+
+/// The panic handler, history and policy of a pool.
+pub(crate) struct PanicLog {
+    handler: Mutex<Option<PanicHandler>>,
+    recent: Mutex<VecDeque<PanicInfo>>,
+    history: AtomicUsize,
+    backtraces: AtomicBool,
+    silent: AtomicBool,
+    catch: AtomicBool,
+    policy: Mutex<PanicPolicy>,
+    // The payload of the panic to re-raise with `PanicPolicy::Propagate`.
+    propagated: Mutex<Option<Box<dyn Any + Send>>>,
+}
+
+/// What the panic hook does for the job running on the current thread.
+struct Capture {
+    backtrace: bool,
+    silent: bool,
+    captured: Option<String>,
+}
+
+thread_local! {
+    static CAPTURE: RefCell<Option<Capture>> = const { RefCell::new(None) };
+}
+
+static INSTALL_HOOK: Once = Once::new();
+
+fn install_hook() {
+    INSTALL_HOOK.call_once(|| {
+        let previous = panic::take_hook();
+        panic::set_hook(Box::new(move |info| {
+            let silent = CAPTURE.with(|capture| match capture.try_borrow_mut() {
+                Ok(mut capture) => {
+                    match *capture {
+                        Some(ref mut capture) => {
+                            if capture.backtrace {
+                                capture.captured = Some(Backtrace::force_capture().to_string());
+                            }
+                            capture.silent
+                        }
+                        None => false,
+                    }
+                }
+                Err(_) => false,
+            });
+            if !silent {
+                previous(info);
+            }
+        }));
+    });
+}
+
+/// Clears the capture of the current thread when the job has finished.
+struct CaptureGuard;
+
+impl Drop for CaptureGuard {
+    fn drop(&mut self) {
+        CAPTURE.with(|capture| capture.borrow_mut().take());
+    }
+}
+
+impl PanicLog {
+    pub fn new() -> PanicLog {
+        PanicLog {
+            handler: Mutex::new(None),
+            recent: Mutex::new(VecDeque::new()),
+            history: AtomicUsize::new(DEFAULT_PANIC_HISTORY),
+            backtraces: AtomicBool::new(false),
+            silent: AtomicBool::new(false),
+            catch: AtomicBool::new(false),
+            policy: Mutex::new(PanicPolicy::Respawn),
+            propagated: Mutex::new(None),
+        }
+    }
+
+    /// Returns `true` if workers survive the panics of their jobs.
+    pub fn catches_panics(&self) -> bool {
+        self.catch.load(Ordering::Relaxed)
+    }
+
+    pub fn policy(&self) -> PanicPolicy {
+        *self.policy.lock().unwrap()
+    }
+
+    /// Re-raises the panic of a job on the current thread, if there is one
+    /// waiting to be propagated.
+    pub fn propagate(&self) {
+        let payload = self.propagated.lock().unwrap().take();
+        if let Some(payload) = payload {
+            panic::resume_unwind(payload);
+        }
+    }
+
+    fn capture(&self) -> Option<CaptureGuard> {
+        let backtrace = self.backtraces.load(Ordering::Relaxed);
+        let silent = self.silent.load(Ordering::Relaxed);
+        if !backtrace && !silent {
+            return None;
+        }
+        CAPTURE.with(|capture| {
+            *capture.borrow_mut() = Some(Capture {
+                backtrace,
+                silent,
+                captured: None,
+            });
+        });
+        Some(CaptureGuard)
+    }
+
+    fn record(&self, info: PanicInfo) {
+        {
+            let history = self.history.load(Ordering::Relaxed);
+            let mut recent = self.recent.lock().unwrap();
+            if history > 0 {
+                if recent.len() >= history {
+                    recent.pop_front();
+                }
+                recent.push_back(info.clone());
+            }
+        }
+        let handler = self.handler.lock().unwrap().clone();
+        if let Some(handler) = handler {
+            let _ = panic::catch_unwind(AssertUnwindSafe(|| handler(info)));
+        }
+    }
+}
+
+/// Runs `job`, reporting it to the panic log of the pool if it panics. The
+/// panic is then handled according to the panic policy of the pool and
+/// resumed.
+pub(crate) fn run_reporting_panics(shared_data: &ThreadPoolSharedData, worker: Option<usize>, job: Job) {
+    let log = &shared_data.panic_log;
+    let job_name = job.name().map(|name| name.to_owned());
+    let capture = log.capture();
+    if let Err(payload) = panic::catch_unwind(AssertUnwindSafe(|| job.run())) {
+        let backtrace = capture.and_then(|_guard| {
+            CAPTURE.with(|capture| capture.borrow_mut().as_mut().and_then(|capture| capture.captured.take()))
+        });
+        let message = handle::panic_message(&*payload).map(|message| message.to_owned());
+        trace::job_panicked(shared_data,
+                            worker,
+                            job_name.as_ref().map(|name| &name[..]),
+                            message.as_ref().map(|message| &message[..]));
+        log.record(PanicInfo {
+            message: message.clone(),
+            thread_name: thread::current().name().map(|name| name.to_owned()),
+            job_name,
+            backtrace,
+        });
+        match log.policy() {
+            PanicPolicy::Respawn => panic::resume_unwind(payload),
+            PanicPolicy::Shutdown => {
+                drop(shared_data.shutdown_now());
+                panic::resume_unwind(payload);
+            }
+            PanicPolicy::Abort => process::abort(),
+            PanicPolicy::Propagate => {
+                {
+                    let mut propagated = log.propagated.lock().unwrap();
+                    if propagated.is_none() {
+                        *propagated = Some(payload);
+                    }
+                }
+                // The payload is kept for the waiting caller, so the worker
+                // unwinds with a copy of the message.
+                let message = message.unwrap_or_else(|| "job panicked".to_owned());
+                panic::resume_unwind(Box::new(message));
+            }
+        }
+    }
+}
+
+impl ThreadPool {
+    /// Sets a handler that is called with a description of every job that
+    /// panics, replacing any previous handler.
+    ///
+    /// The handler runs on the worker thread of the job before the worker
+    /// unwinds. A panic in the handler is ignored.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use threadpool::{JobBuilder, ThreadPool};
+    /// use std::sync::mpsc::channel;
+    /// use std::sync::Mutex;
+    ///
+    /// let mut pool = ThreadPool::new(1);
+    /// let (tx, rx) = channel();
+    /// let tx = Mutex::new(tx);
+    /// pool.on_panic(move |info| tx.lock().unwrap().send(info).unwrap());
+    /// pool.set_panic_output(false);
+    ///
+    /// JobBuilder::new().name("parse").execute(&pool, || panic!("unexpected token"));
+    ///
+    /// let info = rx.recv().unwrap();
+    /// assert_eq!(info.message(), Some("unexpected token"));
+    /// assert_eq!(info.job_name(), Some("parse"));
+    /// ```
+    pub fn on_panic<F>(&mut self, handler: F)
+        where F: Fn(PanicInfo) + Send + Sync + 'static
+    {
+        *self.shared_data.panic_log.handler.lock().unwrap() = Some(Arc::new(handler));
+    }
+
+    /// Returns the most recent panics of jobs in the pool, oldest first.
+    ///
+    /// The pool remembers the last 16 panics unless configured otherwise with
+    /// [`set_panic_history`](#method.set_panic_history).
+    pub fn recent_panics(&self) -> Vec<PanicInfo> {
+        self.shared_data.panic_log.recent.lock().unwrap().iter().cloned().collect()
+    }
+
+    /// Sets how many panics [`recent_panics`](#method.recent_panics) returns
+    /// at most. A length of 0 disables the history.
+    pub fn set_panic_history(&mut self, len: usize) {
+        self.shared_data.panic_log.history.store(len, Ordering::Relaxed);
+        let mut recent = self.shared_data.panic_log.recent.lock().unwrap();
+        while recent.len() > len {
+            recent.pop_front();
+        }
+    }
+
+    /// Sets whether a backtrace is captured for every panicking job. Disabled
+    /// by default.
+    ///
+    /// Capturing backtraces installs a process-wide panic hook, see
+    /// [`set_panic_output`](#method.set_panic_output).
+    pub fn set_panic_backtraces(&mut self, enabled: bool) {
+        if enabled {
+            install_hook();
+        }
+        self.shared_data.panic_log.backtraces.store(enabled, Ordering::Relaxed);
+    }
+
+    /// Sets whether panicking jobs print the default panic message to
+    /// stderr. Enabled by default.
+    ///
+    /// Disabling the output is useful for pools that run jobs which are
+    /// expected to panic. It installs a process-wide panic hook, which is
+    /// chained to the hook that was set before and only silences panics of
+    /// jobs running in pools that asked for it.
+    pub fn set_panic_output(&mut self, enabled: bool) {
+        if !enabled {
+            install_hook();
+        }
+        self.shared_data.panic_log.silent.store(!enabled, Ordering::Relaxed);
+    }
+
+    /// Sets whether worker threads survive the panics of their jobs. Disabled
+    /// by default.
+    ///
+    /// Normally a panicking job unwinds its worker thread, which is then
+    /// replaced by a new thread. When enabled, the worker catches the panic,
+    /// counts it in [`panic_count`](#method.panic_count) and goes on with the
+    /// next job on the same thread. This avoids spawning a thread for every
+    /// panic, but the state of thread-local variables is kept as the job left
+    /// it.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use threadpool::ThreadPool;
+    /// use std::sync::mpsc::channel;
+    /// use std::thread;
+    ///
+    /// let mut pool = ThreadPool::new(1);
+    /// pool.set_catch_panics(true);
+    /// pool.set_panic_output(false);
+    ///
+    /// let (tx, rx) = channel();
+    /// for _ in 0..3 {
+    ///     let tx = tx.clone();
+    ///     pool.execute(move || {
+    ///         tx.send(thread::current().id()).unwrap();
+    ///         panic!("malformed input");
+    ///     });
+    /// }
+    ///
+    /// assert!(pool.join());
+    /// assert_eq!(pool.panic_count(), 3);
+    /// let first = rx.recv().unwrap();
+    /// assert!(rx.iter().take(2).all(|id| id == first));
+    /// ```
+    pub fn set_catch_panics(&mut self, enabled: bool) {
+        self.shared_data.panic_log.catch.store(enabled, Ordering::Relaxed);
+    }
+
+    /// Sets what the pool does after one of its jobs panicked. The default is
+    /// [`PanicPolicy::Respawn`](enum.PanicPolicy.html#variant.Respawn).
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use threadpool::{PanicPolicy, ThreadPool};
+    /// use std::panic::{self, AssertUnwindSafe};
+    ///
+    /// let mut pool = ThreadPool::new(2);
+    /// pool.set_panic_policy(PanicPolicy::Propagate);
+    /// pool.set_panic_output(false);
+    ///
+    /// pool.execute(|| panic!("invalid input"));
+    /// let result = panic::catch_unwind(AssertUnwindSafe(|| pool.join()));
+    /// let payload = result.unwrap_err();
+    /// assert_eq!(payload.downcast_ref::<&str>(), Some(&"invalid input"));
+    ///
+    /// // The panic is only re-raised once.
+    /// assert!(!pool.join());
+    /// ```
+    pub fn set_panic_policy(&mut self, policy: PanicPolicy) {
+        *self.shared_data.panic_log.policy.lock().unwrap() = policy;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::{JobBuilder, PanicPolicy, ThreadPool};
+    use std::panic::{self, AssertUnwindSafe};
+    use std::sync::mpsc::channel;
+    use std::sync::Mutex;
+    use std::thread::{self, sleep};
+    use std::time::Duration;
+
+    #[test]
+    fn test_on_panic_reports_job() {
+        let mut pool = ThreadPool::new_with_name("panicky".into(), 1);
+        let (tx, rx) = channel();
+        let tx = Mutex::new(tx);
+        pool.on_panic(move |info| tx.lock().unwrap().send(info).unwrap());
+        pool.set_panic_output(false);
+        pool.set_panic_backtraces(true);
+
+        JobBuilder::new().name("first").execute(&pool, || panic!("first failure"));
+        pool.execute(|| panic!("second failure: {}", 2));
+
+        let first = rx.recv().unwrap();
+        assert_eq!(first.message(), Some("first failure"));
+        assert_eq!(first.thread_name(), Some("panicky"));
+        assert_eq!(first.job_name(), Some("first"));
+        assert!(first.backtrace().is_some());
+        let second = rx.recv().unwrap();
+        assert_eq!(second.message(), Some("second failure: 2"));
+        assert_eq!(second.job_name(), None);
+
+        pool.join();
+        assert_eq!(pool.panic_count(), 2);
+    }
+
+    #[test]
+    fn test_recent_panics_are_bounded() {
+        let mut pool = ThreadPool::new(2);
+        pool.set_panic_output(false);
+        pool.set_panic_history(3);
+        for i in 0..5 {
+            pool.execute(move || panic!("panic {}", i));
+            pool.join();
+        }
+
+        let recent = pool.recent_panics();
+        let messages: Vec<_> = recent.iter().map(|info| info.message().unwrap()).collect();
+        assert_eq!(messages, vec!["panic 2", "panic 3", "panic 4"]);
+        assert!(recent[0].backtrace().is_none());
+
+        pool.set_panic_history(1);
+        assert_eq!(pool.recent_panics().len(), 1);
+    }
+
+    #[test]
+    fn test_spawned_job_panic_is_reported() {
+        let mut pool = ThreadPool::new(1);
+        pool.set_panic_output(false);
+        let handle = pool.spawn(|| -> usize { panic!("spawned failure") });
+        assert!(handle.join().is_err());
+        pool.join();
+        let recent = pool.recent_panics();
+        assert_eq!(recent.len(), 1);
+        assert_eq!(recent[0].message(), Some("spawned failure"));
+    }
+
+    #[test]
+    fn test_shutdown_policy() {
+        let mut pool = ThreadPool::new(1);
+        pool.set_panic_policy(PanicPolicy::Shutdown);
+        pool.set_panic_output(false);
+        pool.execute(|| {
+            sleep(Duration::from_millis(20));
+            panic!("fail fast");
+        });
+        pool.execute(|| panic!("never runs"));
+
+        assert!(pool.await_termination(Duration::from_secs(10)));
+        assert!(pool.is_shutdown());
+        assert_eq!(pool.panic_count(), 1);
+        assert_eq!(pool.spawned_count(), 0);
+        assert_eq!(pool.completed_count(), 0);
+    }
+
+    #[test]
+    fn test_propagate_policy() {
+        let mut pool = ThreadPool::new(2);
+        pool.set_panic_policy(PanicPolicy::Propagate);
+        pool.set_panic_output(false);
+        for i in 0..3 {
+            pool.execute(move || panic!("propagated {}", i));
+        }
+
+        let payload = panic::catch_unwind(AssertUnwindSafe(|| pool.join())).unwrap_err();
+        assert!(payload.downcast_ref::<String>().unwrap().starts_with("propagated"));
+        assert!(!pool.join());
+        assert_eq!(pool.panic_count(), 3);
+        assert_eq!(pool.max_count(), 2);
+
+        // The pool keeps working.
+        let (tx, rx) = channel();
+        pool.execute(move || tx.send(1).unwrap());
+        assert_eq!(rx.recv(), Ok(1));
+    }
+
+    #[test]
+    fn test_catch_panics_keeps_worker() {
+        let mut pool = ThreadPool::new(1);
+        pool.set_catch_panics(true);
+        pool.set_panic_output(false);
+        let first = pool.spawn(|| thread::current().id()).join().unwrap();
+        for _ in 0..10 {
+            pool.execute(|| panic!("caught"));
+        }
+        assert!(pool.spawn(|| -> usize { panic!("caught too") }).join().is_err());
+        let last = pool.spawn(|| thread::current().id()).join().unwrap();
+
+        assert_eq!(first, last);
+        assert!(pool.join());
+        assert_eq!(pool.panic_count(), 11);
+        assert_eq!(pool.spawned_count(), 1);
+        assert_eq!(pool.recent_panics().len(), 11);
+    }
+}
@@ -0,0 +1,395 @@
+// Copyright 2014 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Metrics in the Prometheus text exposition format, enabled by the
+//! `prometheus` feature.
+//!
+//! Besides the counters of [`PoolStats`], the pool keeps a histogram of how
+//! long its jobs run. Every metric carries the name of the pool as the `pool`
+//! label.
+//!
+//! [`PoolStats`]: ../struct.PoolStats.html
+
+use std::fmt::Write as FmtWrite;
+use std::io::{self, Read, Write};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Weak};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use super::{index, stats, ThreadPool, ThreadPoolSharedData};
+
+/// Upper bounds of the buckets of the job duration histogram, in seconds.
+const DURATION_BUCKETS: [f64; 11] = [0.0001, 0.0005, 0.001, 0.005, 0.01, 0.05, 0.1, 0.5, 1.0, 5.0, 10.0];
+
+/// How long the server waits for a scraper to send its request.
+const READ_TIMEOUT_MS: u64 = 1000;
+
+/// A histogram of how long jobs run.
+pub(crate) struct JobDurations {
+    // Jobs per bucket, not cumulative. The last bucket is `+Inf`.
+    buckets: [AtomicU64; 12],
+    sum_nanos: AtomicU64,
+}
+
+impl JobDurations {
+    pub fn new() -> JobDurations {
+        JobDurations {
+            buckets: Default::default(),
+            sum_nanos: AtomicU64::new(0),
+        }
+    }
+
+    /// Starts timing a job, which is recorded when the returned guard is
+    /// dropped, also if the job panics.
+    pub fn time(&self) -> JobTimer<'_> {
+        JobTimer {
+            durations: self,
+            started: Instant::now(),
+        }
+    }
+
+    fn record(&self, duration: Duration) {
+        let seconds = duration.as_secs_f64();
+        let bucket = DURATION_BUCKETS.iter()
+            .position(|&bound| seconds <= bound)
+            .unwrap_or(DURATION_BUCKETS.len());
+        self.buckets[bucket].fetch_add(1, Ordering::Relaxed);
+        let nanos = duration.as_nanos().min(u64::MAX as u128) as u64;
+        self.sum_nanos.fetch_add(nanos, Ordering::Relaxed);
+    }
+}
+
+pub(crate) struct JobTimer<'a> {
+    durations: &'a JobDurations,
+    started: Instant,
+}
+
+impl<'a> Drop for JobTimer<'a> {
+    fn drop(&mut self) {
+        self.durations.record(self.started.elapsed());
+    }
+}
+
+/// Returns `value` escaped for use as a label value.
+fn escape_label(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            '"' => escaped.push_str("\\\""),
+            '\n' => escaped.push_str("\\n"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Returns the `pool` label of a pool: its name without the `{}` of an
+/// indexed thread name, or an empty string for unnamed pools.
+fn pool_label(name: Option<&str>) -> String {
+    escape_label(&index::pool_name(name))
+}
+
+fn render(shared_data: &ThreadPoolSharedData) -> String {
+    let stats = stats::snapshot(shared_data);
+    let label = format!("pool=\"{}\"", pool_label(shared_data.name.as_ref().map(|name| &name[..])));
+    let mut out = String::new();
+
+    let metrics: [(&str, &str, &str, usize); 13] = [
+        ("threadpool_jobs_queued", "gauge", "Jobs waiting to be run.", stats.queued()),
+        ("threadpool_jobs_active", "gauge", "Jobs being run.", stats.active()),
+        ("threadpool_threads", "gauge", "Worker threads.", stats.threads()),
+        ("threadpool_max_threads",
+         "gauge",
+         "Maximum number of worker threads.",
+         shared_data.max_count.load(Ordering::Relaxed)),
+        ("threadpool_jobs_submitted_total", "counter", "Jobs accepted by the pool.", stats.submitted()),
+        ("threadpool_jobs_completed_total", "counter", "Jobs that ran to completion.", stats.completed()),
+        ("threadpool_jobs_panicked_total", "counter", "Jobs that panicked.", stats.panicked()),
+        ("threadpool_jobs_cancelled_total",
+         "counter",
+         "Jobs dropped without running because they were cancelled.",
+         stats.cancelled()),
+        ("threadpool_threads_spawned_total", "counter", "Worker threads spawned.", stats.threads_spawned()),
+        ("threadpool_threads_retired_total", "counter", "Worker threads that exited.", stats.threads_retired()),
+        ("threadpool_jobs_queued_peak",
+         "gauge",
+         "Largest number of jobs that waited to be run at the same time.",
+         stats.peak_queued()),
+        ("threadpool_jobs_active_peak",
+         "gauge",
+         "Largest number of jobs that were run at the same time.",
+         stats.peak_active()),
+        ("threadpool_watchdog_compensating_threads",
+         "gauge",
+         "Extra threads allowed for jobs over their time budget.",
+         shared_data.compensating_count.load(Ordering::Relaxed)),
+    ];
+    for &(name, kind, help, value) in metrics.iter() {
+        let _ = writeln!(out, "# HELP {} {}", name, help);
+        let _ = writeln!(out, "# TYPE {} {}", name, kind);
+        let _ = writeln!(out, "{}{{{}}} {}", name, label, value);
+    }
+
+    let durations = &shared_data.job_durations;
+    let name = "threadpool_job_duration_seconds";
+    let _ = writeln!(out, "# HELP {} How long jobs ran.", name);
+    let _ = writeln!(out, "# TYPE {} histogram", name);
+    let mut count = 0;
+    for (i, bucket) in durations.buckets.iter().enumerate() {
+        count += bucket.load(Ordering::Relaxed);
+        let bound = match DURATION_BUCKETS.get(i) {
+            Some(bound) => bound.to_string(),
+            None => "+Inf".to_owned(),
+        };
+        let _ = writeln!(out, "{}_bucket{{{},le=\"{}\"}} {}", name, label, bound, count);
+    }
+    let sum = durations.sum_nanos.load(Ordering::Relaxed) as f64 / 1e9;
+    let _ = writeln!(out, "{}_sum{{{}}} {}", name, label, sum);
+    let _ = writeln!(out, "{}_count{{{}}} {}", name, label, count);
+    out
+}
+
+/// A server for the metrics of a pool, started with
+/// [`ThreadPool::serve_prometheus`].
+///
+/// The server stops when it is dropped. It holds no reference to the pool
+/// that keeps it alive; once the pool has been dropped, it answers requests
+/// with `503 Service Unavailable`.
+///
+/// [`ThreadPool::serve_prometheus`]: struct.ThreadPool.html#method.serve_prometheus
+#[derive(Debug)]
+pub struct MetricsServer {
+    local_addr: SocketAddr,
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl MetricsServer {
+    /// Returns the address the server listens on.
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+}
+
+impl Drop for MetricsServer {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+        // Wake the server up from `accept`.
+        let _ = TcpStream::connect(loopback(self.local_addr));
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// The address to connect to a server listening on `addr`: the loopback
+/// address of the same family if `addr` is unspecified, which not every
+/// platform accepts as a destination.
+fn loopback(mut addr: SocketAddr) -> SocketAddr {
+    if addr.ip().is_unspecified() {
+        addr.set_ip(match addr.ip() {
+            IpAddr::V4(_) => IpAddr::V4(Ipv4Addr::LOCALHOST),
+            IpAddr::V6(_) => IpAddr::V6(Ipv6Addr::LOCALHOST),
+        });
+    }
+    addr
+}
+
+fn respond(mut stream: TcpStream, shared_data: &Weak<ThreadPoolSharedData>) -> io::Result<()> {
+    stream.set_read_timeout(Some(Duration::from_millis(READ_TIMEOUT_MS)))?;
+    // Read the request up to the end of its headers; every path is answered
+    // with the metrics.
+    let mut request = Vec::new();
+    let mut buffer = [0; 1024];
+    while !request.ends_with(b"\r\n\r\n") && request.len() < 64 * 1024 {
+        match stream.read(&mut buffer)? {
+            0 => break,
+            n => request.extend_from_slice(&buffer[..n]),
+        }
+    }
+    let (status, body) = match shared_data.upgrade() {
+        Some(shared_data) => ("200 OK", render(&shared_data)),
+        None => ("503 Service Unavailable", "thread pool has been dropped\n".to_owned()),
+    };
+    write!(stream,
+           "HTTP/1.1 {}\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: \
+            {}\r\nConnection: close\r\n\r\n{}",
+           status,
+           body.len(),
+           body)?;
+    stream.flush()
+}
+
+impl ThreadPool {
+    /// Returns the metrics of the pool in the Prometheus text exposition
+    /// format.
+    ///
+    /// Every metric is labelled with the name of the pool, without the `{}`
+    /// of an [indexed](struct.ThreadPoolBuilder.html#method.thread_name) name.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use threadpool::ThreadPool;
+    ///
+    /// let pool = ThreadPool::new_with_name("ingest".into(), 2);
+    /// pool.execute(|| {});
+    /// pool.join();
+    ///
+    /// let metrics = pool.render_prometheus();
+    /// assert!(metrics.contains("threadpool_jobs_completed_total{pool=\"ingest\"} 1\n"));
+    /// ```
+    pub fn render_prometheus(&self) -> String {
+        render(&self.shared_data)
+    }
+
+    /// Serves the metrics of the pool over HTTP on `addr`, for Prometheus to
+    /// scrape. Every request is answered with the output of
+    /// [`render_prometheus`](#method.render_prometheus).
+    ///
+    /// The server runs on a thread of its own until the returned
+    /// [`MetricsServer`] is dropped. Binding to port 0 picks a free port.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use threadpool::ThreadPool;
+    /// use std::io::{Read, Write};
+    /// use std::net::TcpStream;
+    ///
+    /// let pool = ThreadPool::new_with_name("ingest".into(), 2);
+    /// let server = pool.serve_prometheus("127.0.0.1:0").unwrap();
+    ///
+    /// let mut stream = TcpStream::connect(server.local_addr()).unwrap();
+    /// stream.write_all(b"GET /metrics HTTP/1.1\r\n\r\n").unwrap();
+    /// let mut response = String::new();
+    /// stream.read_to_string(&mut response).unwrap();
+    /// assert!(response.starts_with("HTTP/1.1 200 OK"));
+    /// ```
+    ///
+    /// [`MetricsServer`]: struct.MetricsServer.html
+    pub fn serve_prometheus<A: ToSocketAddrs>(&self, addr: A) -> io::Result<MetricsServer> {
+        let listener = TcpListener::bind(addr)?;
+        let local_addr = listener.local_addr()?;
+        let stop = Arc::new(AtomicBool::new(false));
+        let shared_data = Arc::downgrade(&self.shared_data);
+        let mut builder = thread::Builder::new();
+        if let Some(ref name) = self.shared_data.name {
+            builder = builder.name(index::helper_thread_name(name, "metrics"));
+        }
+        let thread = {
+            let stop = stop.clone();
+            builder.spawn(move || for stream in listener.incoming() {
+                    if stop.load(Ordering::SeqCst) {
+                        break;
+                    }
+                    if let Ok(stream) = stream {
+                        let _ = respond(stream, &shared_data);
+                    }
+                })?
+        };
+        Ok(MetricsServer {
+            local_addr,
+            stop,
+            thread: Some(thread),
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::{ThreadPool, ThreadPoolBuilder};
+    use super::{loopback, pool_label};
+    use std::io::{Read, Write};
+    use std::net::TcpStream;
+    use std::thread::sleep;
+    use std::time::Duration;
+
+    fn scrape(server: &super::MetricsServer) -> String {
+        let mut stream = TcpStream::connect(loopback(server.local_addr())).unwrap();
+        stream.write_all(b"GET /metrics HTTP/1.1\r\nHost: localhost\r\n\r\n").unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        response
+    }
+
+    #[test]
+    fn test_render_prometheus() {
+        let mut pool = ThreadPoolBuilder::new().thread_name("ingest-{}").num_threads(2).build().unwrap();
+        pool.set_panic_output(false);
+        for _ in 0..3 {
+            pool.execute(|| {});
+        }
+        pool.execute(|| panic!("counted"));
+        pool.join();
+
+        let metrics = pool.render_prometheus();
+        assert!(metrics.contains("# TYPE threadpool_jobs_submitted_total counter\n"));
+        assert!(metrics.contains("threadpool_jobs_submitted_total{pool=\"ingest\"} 4\n"));
+        assert!(metrics.contains("threadpool_jobs_completed_total{pool=\"ingest\"} 3\n"));
+        assert!(metrics.contains("threadpool_jobs_panicked_total{pool=\"ingest\"} 1\n"));
+        assert!(metrics.contains("threadpool_max_threads{pool=\"ingest\"} 2\n"));
+        assert!(metrics.contains("# TYPE threadpool_job_duration_seconds histogram\n"));
+        assert!(metrics.contains("threadpool_job_duration_seconds_bucket{pool=\"ingest\",le=\"+Inf\"} 4\n"));
+        assert!(metrics.contains("threadpool_job_duration_seconds_count{pool=\"ingest\"} 4\n"));
+    }
+
+    #[test]
+    fn test_pool_label() {
+        assert_eq!(pool_label(None), "");
+        assert_eq!(pool_label(Some("ingest")), "ingest");
+        assert_eq!(pool_label(Some("ingest-{}")), "ingest");
+        assert_eq!(pool_label(Some("{}-ingest")), "ingest");
+        assert_eq!(pool_label(Some("db-{}-io")), "db-io");
+        assert_eq!(pool_label(Some("say \"hi\"\n")), "say \\\"hi\\\"\\n");
+    }
+
+    #[test]
+    fn test_serve_prometheus() {
+        let pool = ThreadPool::new_with_name("served".into(), 1);
+        pool.execute(|| {});
+        pool.join();
+        let server = pool.serve_prometheus("127.0.0.1:0").unwrap();
+
+        let response = scrape(&server);
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.contains("threadpool_jobs_completed_total{pool=\"served\"} 1\n"));
+
+        // The worker lets go of the pool shortly after the pool terminated.
+        pool.shutdown();
+        assert!(pool.await_termination(Duration::from_secs(10)));
+        drop(pool);
+        let mut response = scrape(&server);
+        for _ in 0..100 {
+            if !response.starts_with("HTTP/1.1 200 OK") {
+                break;
+            }
+            sleep(Duration::from_millis(10));
+            response = scrape(&server);
+        }
+        assert!(response.starts_with("HTTP/1.1 503 Service Unavailable\r\n"));
+        drop(server);
+    }
+
+    #[test]
+    fn test_serve_prometheus_on_all_interfaces() {
+        let pool = ThreadPool::new_with_name("everywhere".into(), 1);
+        let server = pool.serve_prometheus("0.0.0.0:0").unwrap();
+        assert!(server.local_addr().ip().is_unspecified());
+
+        let response = scrape(&server);
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        // Stops the server through the loopback address.
+        drop(server);
+    }
+}
@@ -0,0 +1,926 @@
+// Copyright 2014 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! The queue jobs wait in until a worker picks them up.
+
+use std::collections::VecDeque;
+use std::fmt;
+use std::sync::atomic::{self, AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+use super::Job;
+
+/// A job waiting in the queue of a pool.
+///
+/// Stored by implementations of [`JobQueue`] and returned by
+/// [`ThreadPool::shutdown_now`]. Dropping a `PendingJob` discards the job
+/// without running it.
+///
+/// [`JobQueue`]: trait.JobQueue.html
+/// [`ThreadPool::shutdown_now`]: struct.ThreadPool.html#method.shutdown_now
+pub struct PendingJob {
+    job: Job,
+    priority: Priority,
+}
+
+impl PendingJob {
+    pub(crate) fn new(job: Job, priority: Priority) -> PendingJob {
+        PendingJob { job, priority }
+    }
+
+    pub(crate) fn into_job(self) -> Job {
+        self.job
+    }
+
+    /// Runs the job on the current thread, unless it has been cancelled.
+    pub fn run(self) {
+        if !self.job.is_cancelled() {
+            self.job.run();
+        }
+    }
+
+    /// Returns `true` if the cancellation token of the job has been cancelled.
+    pub fn is_cancelled(&self) -> bool {
+        self.job.is_cancelled()
+    }
+}
+
+impl fmt::Debug for PendingJob {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("PendingJob").finish()
+    }
+}
+
+/// The priority of a job.
+///
+/// Workers always take the job with the highest priority first, but every job
+/// gains one level of priority for each [aging interval] it spends waiting, so
+/// that low priority jobs are not starved by a steady stream of more urgent
+/// ones.
+///
+/// Priorities are honored by the default queue of a pool. A pool created with
+/// [`ThreadPool::with_job_queue`] leaves them to its [`JobQueue`].
+///
+/// [aging interval]: struct.ThreadPool.html#method.set_aging_interval
+/// [`ThreadPool::with_job_queue`]: struct.ThreadPool.html#method.with_job_queue
+/// [`JobQueue`]: trait.JobQueue.html
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Priority {
+    /// Background work that can wait.
+    Low,
+    /// The priority of jobs submitted with `execute`.
+    #[default]
+    Normal,
+    /// Latency-critical work.
+    High,
+}
+
+const PRIORITY_LEVELS: usize = 3;
+
+/// The default time after which a waiting job gains one level of priority.
+const DEFAULT_AGING_INTERVAL_MS: u64 = 500;
+
+/// What a pool does with a new job when its queue is full.
+///
+/// Only relevant for pools with a bounded queue, see
+/// [`ThreadPool::set_queue_capacity`].
+///
+/// [`ThreadPool::set_queue_capacity`]: struct.ThreadPool.html#method.set_queue_capacity
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RejectionPolicy {
+    /// Block the submitting thread until there is room in the queue.
+    Block,
+    /// Refuse the job with [`PoolError::QueueFull`].
+    ///
+    /// [`PoolError::QueueFull`]: enum.PoolError.html#variant.QueueFull
+    Abort,
+    /// Run the job on the submitting thread instead.
+    CallerRuns,
+    /// Silently drop the new job.
+    DiscardNewest,
+    /// Drop the job that has been waiting the longest to make room for the new one.
+    DiscardOldest,
+}
+
+/// The storage behind the queue of a pool.
+///
+/// The pool implements shutting down, waiting for jobs, its capacity limit and
+/// the [`RejectionPolicy`] on top of its `JobQueue`, so an implementation only
+/// has to store jobs and hand them out again. It is accessed concurrently by
+/// every thread submitting to the pool and by every worker.
+///
+/// By default a pool uses a queue that honors [`Priority`] and aging. The
+/// crate also ships [`RingQueue`], a bounded lock-free queue.
+///
+/// # Example
+///
+/// ```rust
+/// use threadpool::{JobQueue, PendingJob, Priority, ThreadPool};
+/// use std::sync::Mutex;
+///
+/// /// Runs the most recently submitted job first.
+/// #[derive(Default)]
+/// struct Lifo(Mutex<Vec<PendingJob>>);
+///
+/// impl JobQueue for Lifo {
+///     fn push(&self, job: PendingJob, _priority: Priority) -> Result<(), PendingJob> {
+///         self.0.lock().unwrap().push(job);
+///         Ok(())
+///     }
+///
+///     fn pop(&self) -> Option<PendingJob> {
+///         self.0.lock().unwrap().pop()
+///     }
+/// }
+///
+/// let pool = ThreadPool::with_job_queue(2, Lifo::default());
+/// pool.execute(|| println!("hello from a lifo pool"));
+/// pool.join();
+/// ```
+///
+/// [`RejectionPolicy`]: enum.RejectionPolicy.html
+/// [`Priority`]: enum.Priority.html
+/// [`RingQueue`]: struct.RingQueue.html
+pub trait JobQueue: Send + Sync {
+    /// Adds `job` to the queue, or hands it back if the queue is full.
+    fn push(&self, job: PendingJob, priority: Priority) -> Result<(), PendingJob>;
+
+    /// Removes the job that should run next without blocking, or returns
+    /// `None` if the queue is empty.
+    fn pop(&self) -> Option<PendingJob>;
+
+    /// Adds `jobs` in order and returns the ones that did not fit, starting
+    /// with the first job that was refused.
+    ///
+    /// The default implementation pushes the jobs one by one. Queues that
+    /// take a lock should override it to take the lock only once.
+    fn push_batch(&self, jobs: Vec<PendingJob>, priority: Priority) -> Vec<PendingJob> {
+        let mut jobs = jobs.into_iter();
+        for job in jobs.by_ref() {
+            if let Err(job) = self.push(job, priority) {
+                let mut rejected = vec![job];
+                rejected.extend(jobs);
+                return rejected;
+            }
+        }
+        Vec::new()
+    }
+
+    /// Removes up to `max` jobs in the order they should run next.
+    ///
+    /// The default implementation pops the jobs one by one. Queues that take
+    /// a lock should override it to take the lock only once.
+    fn pop_batch(&self, max: usize) -> Vec<PendingJob> {
+        let mut jobs = Vec::new();
+        while jobs.len() < max {
+            match self.pop() {
+                Some(job) => jobs.push(job),
+                None => break,
+            }
+        }
+        jobs
+    }
+
+    /// Removes the job that has been waiting the longest, to make room for a
+    /// new one under [`RejectionPolicy::DiscardOldest`]. Defaults to
+    /// [`pop`](#tymethod.pop).
+    ///
+    /// [`RejectionPolicy::DiscardOldest`]: enum.RejectionPolicy.html#variant.DiscardOldest
+    fn pop_oldest(&self) -> Option<PendingJob> {
+        self.pop()
+    }
+
+    /// Returns the number of jobs the queue can hold, or `None` if it is
+    /// unbounded. Defaults to `None`.
+    ///
+    /// The pool never pushes more jobs than this, so `push` only fails if the
+    /// queue is full for other reasons.
+    fn capacity(&self) -> Option<usize> {
+        None
+    }
+}
+
+/// Why a job could not be added to the queue. The job is handed back, along
+/// with the job that was evicted to make room for it, if any.
+pub(crate) enum PushError {
+    Closed(Job, Option<Job>),
+    Full(Job, Option<Job>),
+}
+
+/// The outcome of waiting for a job in [`Injector::pop`].
+pub(crate) enum Popped {
+    Job(Job),
+    /// The queue is empty, but jobs can be taken elsewhere.
+    Interrupted,
+    /// The queue is closed and empty.
+    Closed,
+    /// The deadline passed before a job became available.
+    TimedOut,
+}
+
+struct QueuedJob {
+    job: PendingJob,
+    enqueued_at: Instant,
+}
+
+struct PriorityState {
+    // One FIFO queue per priority level, lowest priority first.
+    levels: [VecDeque<QueuedJob>; PRIORITY_LEVELS],
+    aging_interval: Duration,
+}
+
+impl PriorityState {
+    /// Returns the level whose oldest job should run next, taking into account
+    /// how long each of them has been waiting.
+    fn next_level(&self, now: Instant) -> Option<usize> {
+        let interval = self.aging_interval.as_nanos();
+        let mut next: Option<(u128, Instant, usize)> = None;
+        for (level, jobs) in self.levels.iter().enumerate() {
+            if let Some(head) = jobs.front() {
+                let waited = now.saturating_duration_since(head.enqueued_at).as_nanos();
+                let rank = level as u128 + waited / interval;
+                // On equal rank the job that has been waiting longer wins.
+                let better = match next {
+                    Some((best_rank, best_enqueued_at, _)) => {
+                        rank > best_rank || (rank == best_rank && head.enqueued_at < best_enqueued_at)
+                    }
+                    None => true,
+                };
+                if better {
+                    next = Some((rank, head.enqueued_at, level));
+                }
+            }
+        }
+        next.map(|(_, _, level)| level)
+    }
+
+    /// Returns the level holding the job that has been waiting the longest.
+    fn oldest_level(&self) -> Option<usize> {
+        self.levels
+            .iter()
+            .enumerate()
+            .filter_map(|(level, jobs)| jobs.front().map(|head| (head.enqueued_at, level)))
+            .min()
+            .map(|(_, level)| level)
+    }
+
+    fn pop_level(&mut self, level: usize) -> PendingJob {
+        self.levels[level].pop_front().unwrap().job
+    }
+}
+
+/// The default queue of a pool: one FIFO queue per priority level, with aging.
+pub(crate) struct PriorityQueue {
+    state: Mutex<PriorityState>,
+}
+
+impl PriorityQueue {
+    pub fn new() -> PriorityQueue {
+        PriorityQueue {
+            state: Mutex::new(PriorityState {
+                levels: [VecDeque::new(), VecDeque::new(), VecDeque::new()],
+                aging_interval: Duration::from_millis(DEFAULT_AGING_INTERVAL_MS),
+            }),
+        }
+    }
+
+    pub fn aging_interval(&self) -> Duration {
+        self.state.lock().unwrap().aging_interval
+    }
+
+    pub fn set_aging_interval(&self, interval: Duration) {
+        self.state.lock().unwrap().aging_interval = interval;
+    }
+}
+
+impl JobQueue for PriorityQueue {
+    fn push(&self, job: PendingJob, priority: Priority) -> Result<(), PendingJob> {
+        self.state.lock().unwrap().levels[priority as usize].push_back(QueuedJob {
+            job,
+            enqueued_at: Instant::now(),
+        });
+        Ok(())
+    }
+
+    fn pop(&self) -> Option<PendingJob> {
+        let mut state = self.state.lock().unwrap();
+        state.next_level(Instant::now()).map(|level| state.pop_level(level))
+    }
+
+    fn push_batch(&self, jobs: Vec<PendingJob>, priority: Priority) -> Vec<PendingJob> {
+        let enqueued_at = Instant::now();
+        self.state.lock().unwrap().levels[priority as usize]
+            .extend(jobs.into_iter().map(|job| QueuedJob { job, enqueued_at }));
+        Vec::new()
+    }
+
+    fn pop_batch(&self, max: usize) -> Vec<PendingJob> {
+        let mut state = self.state.lock().unwrap();
+        let now = Instant::now();
+        let mut jobs = Vec::new();
+        while jobs.len() < max {
+            match state.next_level(now) {
+                Some(level) => jobs.push(state.pop_level(level)),
+                None => break,
+            }
+        }
+        jobs
+    }
+
+    fn pop_oldest(&self) -> Option<PendingJob> {
+        let mut state = self.state.lock().unwrap();
+        state.oldest_level().map(|level| state.pop_level(level))
+    }
+}
+
+/// The queue shared by all workers of a pool, acting as the global injector.
+///
+/// Wraps the [`JobQueue`] of the pool and adds closing, blocking until a job
+/// is available, the capacity limit and the rejection policies.
+pub(crate) struct Injector {
+    queue: Arc<dyn JobQueue>,
+    // The same queue as `queue` if the pool uses the default one.
+    priority_queue: Option<Arc<PriorityQueue>>,
+    // Number of jobs pushed and not yet taken, including pushes in progress.
+    len: AtomicUsize,
+    // Number of those jobs with a priority other than `Normal`.
+    prioritized: AtomicUsize,
+    // The capacity set on the pool, 0 if unbounded.
+    capacity: AtomicUsize,
+    policy: Mutex<RejectionPolicy>,
+    // Taken around waiting on and notifying the condvars.
+    lock: Mutex<()>,
+    available: Condvar,
+    not_full: Condvar,
+    closed: AtomicBool,
+    // Number of workers waiting for a job and of submitters waiting for room.
+    sleepers: AtomicUsize,
+    blocked: AtomicUsize,
+}
+
+/// The job queue set on a [`ThreadPoolBuilder`], if any.
+///
+/// [`ThreadPoolBuilder`]: struct.ThreadPoolBuilder.html
+#[derive(Clone, Default)]
+pub(crate) struct CustomQueue(Option<Arc<dyn JobQueue>>);
+
+impl CustomQueue {
+    pub fn new(queue: Arc<dyn JobQueue>) -> CustomQueue {
+        CustomQueue(Some(queue))
+    }
+
+    /// Creates the injector of a pool around the custom queue, or around a
+    /// priority queue if there is none.
+    pub fn into_injector(self) -> Injector {
+        match self.0 {
+            Some(queue) => Injector::with_queue(queue, None),
+            None => Injector::new(),
+        }
+    }
+}
+
+impl fmt::Debug for CustomQueue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_tuple("CustomQueue").field(&self.0.is_some()).finish()
+    }
+}
+
+impl Injector {
+    pub fn new() -> Injector {
+        let queue = Arc::new(PriorityQueue::new());
+        Injector::with_queue(queue.clone(), Some(queue))
+    }
+
+    pub fn with_queue(queue: Arc<dyn JobQueue>, priority_queue: Option<Arc<PriorityQueue>>) -> Injector {
+        Injector {
+            queue,
+            priority_queue,
+            len: AtomicUsize::new(0),
+            prioritized: AtomicUsize::new(0),
+            capacity: AtomicUsize::new(0),
+            policy: Mutex::new(RejectionPolicy::Block),
+            lock: Mutex::new(()),
+            available: Condvar::new(),
+            not_full: Condvar::new(),
+            closed: AtomicBool::new(false),
+            sleepers: AtomicUsize::new(0),
+            blocked: AtomicUsize::new(0),
+        }
+    }
+
+    /// The number of jobs that fit in the queue: the smaller of the capacity of
+    /// the pool and that of its `JobQueue`.
+    fn limit(&self) -> Option<usize> {
+        let capacity = match self.capacity.load(Ordering::SeqCst) {
+            0 => None,
+            capacity => Some(capacity),
+        };
+        match (capacity, self.queue.capacity()) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        }
+    }
+
+    fn is_full(&self) -> bool {
+        self.limit().is_some_and(|limit| self.len.load(Ordering::SeqCst) >= limit)
+    }
+
+    /// Reserves room for one job, if there is any.
+    fn reserve(&self) -> bool {
+        match self.limit() {
+            Some(limit) => {
+                self.len
+                    .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |len| if len < limit {
+                        Some(len + 1)
+                    } else {
+                        None
+                    })
+                    .is_ok()
+            }
+            None => {
+                self.len.fetch_add(1, Ordering::SeqCst);
+                true
+            }
+        }
+    }
+
+    /// Reserves room for up to `count` jobs and returns for how many.
+    fn reserve_many(&self, count: usize) -> usize {
+        match self.limit() {
+            Some(limit) => {
+                let mut reserved = 0;
+                let _ = self.len.fetch_update(Ordering::SeqCst, Ordering::SeqCst, |len| {
+                    reserved = limit.saturating_sub(len).min(count);
+                    Some(len + reserved)
+                });
+                reserved
+            }
+            None => {
+                self.len.fetch_add(count, Ordering::SeqCst);
+                count
+            }
+        }
+    }
+
+    /// Accounts for a job that was removed from the queue, or could not be
+    /// pushed after all.
+    fn removed(&self, job: &PendingJob) {
+        self.len.fetch_sub(1, Ordering::SeqCst);
+        if job.priority != Priority::Normal {
+            self.prioritized.fetch_sub(1, Ordering::SeqCst);
+        }
+    }
+
+    /// Accounts for a job that was taken from the queue to run.
+    fn taken(&self, job: PendingJob, locked: bool) -> Job {
+        self.removed(&job);
+        if self.blocked.load(Ordering::SeqCst) > 0 {
+            if locked {
+                self.not_full.notify_one();
+            } else {
+                let _lock = self.lock.lock().unwrap();
+                self.not_full.notify_one();
+            }
+        }
+        job.into_job()
+    }
+
+    /// Adds a job to the queue.
+    ///
+    /// If the queue is full, the rejection policy decides whether to wait for
+    /// room, to evict the oldest job, which is then returned, or to hand the
+    /// new job back. An evicted job is also returned if the push fails anyway,
+    /// because another job took the room or the queue was closed meanwhile.
+    /// Unless `blocking` is `true`, the `Block` policy hands the job back like
+    /// `Abort` instead of waiting.
+    #[allow(clippy::result_large_err)]
+    pub fn push(&self,
+                job: Job,
+                priority: Priority,
+                blocking: bool)
+                -> Result<Option<Job>, PushError> {
+        let mut job = PendingJob::new(job, priority);
+        let mut evicted = None;
+        loop {
+            if self.is_closed() {
+                return Err(PushError::Closed(job.into_job(), evicted));
+            }
+            job = match self.insert(job) {
+                Ok(()) => return Ok(evicted),
+                Err(job) => job,
+            };
+            match self.policy() {
+                RejectionPolicy::Block if blocking => {
+                    // Wait once, then try again: the policy may have changed.
+                    let lock = self.lock.lock().unwrap();
+                    self.blocked.fetch_add(1, Ordering::SeqCst);
+                    let _lock = if !self.is_closed() && self.is_full() {
+                        self.not_full.wait(lock).unwrap()
+                    } else {
+                        lock
+                    };
+                    self.blocked.fetch_sub(1, Ordering::SeqCst);
+                }
+                RejectionPolicy::DiscardOldest if evicted.is_none() => {
+                    match self.queue.pop_oldest() {
+                        Some(oldest) => {
+                            self.removed(&oldest);
+                            evicted = Some(oldest.into_job());
+                        }
+                        None => return Err(PushError::Full(job.into_job(), evicted)),
+                    }
+                }
+                _ => return Err(PushError::Full(job.into_job(), evicted)),
+            }
+        }
+    }
+
+    /// Adds a job to the queue if there is room for it, and hands it back
+    /// otherwise, whatever the rejection policy.
+    #[allow(clippy::result_large_err)]
+    pub fn try_push(&self, job: Job, priority: Priority) -> Result<(), PushError> {
+        let job = PendingJob::new(job, priority);
+        if self.is_closed() {
+            return Err(PushError::Closed(job.into_job(), None));
+        }
+        self.insert(job).map_err(|job| PushError::Full(job.into_job(), None))
+    }
+
+    /// Reserves room for a job and adds it to the queue, handing it back if
+    /// there is no room.
+    fn insert(&self, job: PendingJob) -> Result<(), PendingJob> {
+        if !self.reserve() {
+            return Err(job);
+        }
+        // Counted before the push, so that taking the job never precedes
+        // counting it.
+        if job.priority != Priority::Normal {
+            self.prioritized.fetch_add(1, Ordering::SeqCst);
+        }
+        let priority = job.priority;
+        match self.queue.push(job, priority) {
+            Ok(()) => {
+                self.wake_one();
+                Ok(())
+            }
+            Err(rejected) => {
+                self.removed(&rejected);
+                Err(rejected)
+            }
+        }
+    }
+
+    /// Adds as many of `jobs` as fit into the queue in one operation, and wakes
+    /// one waiting worker for each of them. Returns how many jobs were added
+    /// and the jobs that did not fit, or all jobs if the queue is closed.
+    pub fn push_batch(&self,
+                      jobs: Vec<Job>,
+                      priority: Priority)
+                      -> Result<(usize, Vec<Job>), Vec<Job>> {
+        if self.is_closed() {
+            return Err(jobs);
+        }
+        let mut jobs: Vec<PendingJob> = jobs.into_iter().map(|job| PendingJob::new(job, priority)).collect();
+        let reserved = self.reserve_many(jobs.len());
+        let mut rest = jobs.split_off(reserved);
+        if priority != Priority::Normal {
+            self.prioritized.fetch_add(reserved, Ordering::SeqCst);
+        }
+        let mut rejected = self.queue.push_batch(jobs, priority);
+        for job in &rejected {
+            self.removed(job);
+        }
+        let pushed = reserved - rejected.len();
+        self.wake(pushed);
+        rejected.append(&mut rest);
+        Ok((pushed, rejected.into_iter().map(PendingJob::into_job).collect()))
+    }
+
+    /// Takes the next job from the queue without blocking.
+    pub fn try_pop(&self) -> Option<Job> {
+        self.queue.pop().map(|job| self.taken(job, false))
+    }
+
+    /// Takes up to `max` jobs from the queue without blocking, but no more
+    /// than half of the jobs it holds, so that other workers find some too.
+    pub fn try_pop_batch(&self, max: usize) -> Vec<Job> {
+        let max = max.min(self.len.load(Ordering::SeqCst) / 2);
+        if max == 0 {
+            return Vec::new();
+        }
+        self.queue.pop_batch(max).into_iter().map(|job| self.taken(job, false)).collect()
+    }
+
+    /// Returns `true` if the queue holds jobs with a priority other than
+    /// `Normal`, which then decides which job runs next.
+    pub fn has_prioritized(&self) -> bool {
+        self.prioritized.load(Ordering::SeqCst) > 0
+    }
+
+    /// Takes the next job from the queue, blocking until one is available, the
+    /// queue is closed and empty, `interrupt` returns `true` or `deadline` has
+    /// passed.
+    ///
+    /// `interrupt` is checked before blocking and whenever the waiting thread
+    /// is woken by [`wake_one`](#method.wake_one).
+    pub fn pop<F>(&self, interrupt: F, deadline: Option<Instant>) -> Popped
+        where F: Fn() -> bool
+    {
+        if let Some(job) = self.queue.pop() {
+            return Popped::Job(self.taken(job, false));
+        }
+        let mut lock = self.lock.lock().unwrap();
+        loop {
+            // Announce the wait before looking at the queue again, so that a
+            // concurrent `wake_one` either sees the sleeper or is seen here.
+            self.sleepers.fetch_add(1, Ordering::SeqCst);
+            atomic::fence(Ordering::SeqCst);
+            let popped = if let Some(job) = self.queue.pop() {
+                Some(Popped::Job(self.taken(job, true)))
+            } else if interrupt() {
+                Some(Popped::Interrupted)
+            } else if self.is_closed() {
+                Some(Popped::Closed)
+            } else if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                Some(Popped::TimedOut)
+            } else {
+                None
+            };
+            if popped.is_none() {
+                lock = match deadline {
+                    Some(deadline) => {
+                        let timeout = deadline.saturating_duration_since(Instant::now());
+                        self.available.wait_timeout(lock, timeout).unwrap().0
+                    }
+                    None => self.available.wait(lock).unwrap(),
+                };
+            }
+            self.sleepers.fetch_sub(1, Ordering::SeqCst);
+            if let Some(popped) = popped {
+                return popped;
+            }
+        }
+    }
+
+    /// Wakes one thread blocked in [`pop`](#method.pop), if there is any, so
+    /// it looks for work again.
+    pub fn wake_one(&self) {
+        self.wake(1);
+    }
+
+    /// Wakes up to `count` threads blocked in [`pop`](#method.pop).
+    pub fn wake(&self, count: usize) {
+        atomic::fence(Ordering::SeqCst);
+        let sleepers = self.sleepers.load(Ordering::SeqCst);
+        if sleepers > 0 && count > 0 {
+            let _lock = self.lock.lock().unwrap();
+            if count >= sleepers {
+                self.available.notify_all();
+            } else {
+                for _ in 0..count {
+                    self.available.notify_one();
+                }
+            }
+        }
+    }
+
+    /// Stops the queue from accepting new jobs. Jobs already queued can still
+    /// be taken.
+    pub fn close(&self) {
+        let _lock = self.lock.lock().unwrap();
+        self.closed.store(true, Ordering::SeqCst);
+        self.available.notify_all();
+        self.not_full.notify_all();
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.closed.load(Ordering::SeqCst)
+    }
+
+    pub fn is_bounded(&self) -> bool {
+        self.limit().is_some()
+    }
+
+    /// Removes all queued jobs, in the order they would have been taken.
+    pub fn drain(&self) -> Vec<PendingJob> {
+        let mut pending = Vec::new();
+        while let Some(job) = self.queue.pop() {
+            self.removed(&job);
+            pending.push(job);
+        }
+        let _lock = self.lock.lock().unwrap();
+        self.not_full.notify_all();
+        pending
+    }
+
+    pub fn aging_interval(&self) -> Duration {
+        self.priority_queue
+            .as_ref()
+            .map_or(Duration::from_millis(DEFAULT_AGING_INTERVAL_MS),
+                    |queue| queue.aging_interval())
+    }
+
+    pub fn set_aging_interval(&self, interval: Duration) {
+        if let Some(ref queue) = self.priority_queue {
+            queue.set_aging_interval(interval);
+        }
+    }
+
+    pub fn capacity(&self) -> Option<usize> {
+        match self.capacity.load(Ordering::SeqCst) {
+            0 => None,
+            capacity => Some(capacity),
+        }
+    }
+
+    pub fn set_capacity(&self, capacity: Option<usize>) {
+        let _lock = self.lock.lock().unwrap();
+        self.capacity.store(capacity.unwrap_or(0), Ordering::SeqCst);
+        self.not_full.notify_all();
+    }
+
+    pub fn policy(&self) -> RejectionPolicy {
+        *self.policy.lock().unwrap()
+    }
+
+    pub fn set_policy(&self, policy: RejectionPolicy) {
+        *self.policy.lock().unwrap() = policy;
+        // Blocked submitters have to re-evaluate what to do.
+        let _lock = self.lock.lock().unwrap();
+        self.not_full.notify_all();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::{testing, PoolError, Priority, RejectionPolicy, ThreadPool};
+    use std::sync::mpsc::{channel, Sender};
+    use std::sync::{Arc, Mutex};
+    use std::thread::{self, sleep};
+    use std::time::Duration;
+
+    /// Creates a pool with one thread that is kept busy until the returned
+    /// sender is used, and a queue that holds two jobs.
+    fn bounded_pool(policy: RejectionPolicy) -> (ThreadPool, Sender<()>) {
+        let (mut pool, release) = testing::blocked_pool();
+        pool.set_queue_capacity(Some(2));
+        pool.set_rejection_policy(policy);
+        (pool, release)
+    }
+
+    fn record(pool: &ThreadPool, log: &Arc<Mutex<Vec<usize>>>, i: usize) -> Result<(), PoolError> {
+        let log = log.clone();
+        pool.try_execute(move || log.lock().unwrap().push(i))
+    }
+
+    #[test]
+    fn test_block() {
+        let (pool, release) = bounded_pool(RejectionPolicy::Block);
+        let log = Arc::new(Mutex::new(Vec::new()));
+        record(&pool, &log, 0).unwrap();
+        record(&pool, &log, 1).unwrap();
+
+        let (done_tx, done_rx) = channel();
+        let submitter = {
+            let pool = pool.clone();
+            let log = log.clone();
+            thread::spawn(move || {
+                record(&pool, &log, 2).unwrap();
+                done_tx.send(()).unwrap();
+            })
+        };
+
+        // The submitter waits until the worker makes room.
+        assert!(done_rx.recv_timeout(Duration::from_millis(100)).is_err());
+        release.send(()).unwrap();
+        done_rx.recv().unwrap();
+        submitter.join().unwrap();
+
+        pool.join();
+        assert_eq!(*log.lock().unwrap(), vec![0, 1, 2]);
+    }
+
+    #[test]
+    fn test_abort() {
+        let (pool, release) = bounded_pool(RejectionPolicy::Abort);
+        let log = Arc::new(Mutex::new(Vec::new()));
+        record(&pool, &log, 0).unwrap();
+        record(&pool, &log, 1).unwrap();
+        match record(&pool, &log, 2) {
+            Err(PoolError::QueueFull) => {}
+            other => panic!("unexpected result: {:?}", other),
+        }
+
+        release.send(()).unwrap();
+        pool.join();
+        assert_eq!(*log.lock().unwrap(), vec![0, 1]);
+    }
+
+    #[test]
+    fn test_caller_runs() {
+        let (pool, release) = bounded_pool(RejectionPolicy::CallerRuns);
+        let caller = thread::current().id();
+        let (tx, rx) = channel();
+        for _ in 0..3 {
+            let tx = tx.clone();
+            pool.execute(move || tx.send(thread::current().id()).unwrap());
+        }
+
+        // The third job ran right away on this thread.
+        assert_eq!(rx.try_recv().unwrap(), caller);
+        release.send(()).unwrap();
+        assert!(rx.iter().take(2).all(|id| id != caller));
+    }
+
+    #[test]
+    fn test_discard_newest() {
+        let (pool, release) = bounded_pool(RejectionPolicy::DiscardNewest);
+        let log = Arc::new(Mutex::new(Vec::new()));
+        for i in 0..4 {
+            record(&pool, &log, i).unwrap();
+        }
+
+        release.send(()).unwrap();
+        pool.join();
+        assert_eq!(*log.lock().unwrap(), vec![0, 1]);
+    }
+
+    #[test]
+    fn test_discard_oldest() {
+        let (pool, release) = bounded_pool(RejectionPolicy::DiscardOldest);
+        let log = Arc::new(Mutex::new(Vec::new()));
+        for i in 0..4 {
+            record(&pool, &log, i).unwrap();
+        }
+
+        release.send(()).unwrap();
+        pool.join();
+        assert_eq!(*log.lock().unwrap(), vec![2, 3]);
+    }
+
+    #[test]
+    fn test_discard_oldest_racing_producers() {
+        let (pool, release) = bounded_pool(RejectionPolicy::DiscardOldest);
+        let producers: Vec<_> = (0..2)
+            .map(|_| {
+                let pool = pool.clone();
+                thread::spawn(move || for _ in 0..10_000 {
+                    let _ = pool.try_execute(|| {});
+                })
+            })
+            .collect();
+        for producer in producers {
+            producer.join().unwrap();
+        }
+
+        release.send(()).unwrap();
+        pool.join();
+        assert_eq!(pool.stats().queued(), 0);
+    }
+
+    #[test]
+    fn test_priority_order() {
+        let (mut pool, release) = testing::blocked_pool();
+        pool.set_aging_interval(Duration::from_secs(3600));
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let priorities = [Priority::Low, Priority::Normal, Priority::High, Priority::Low, Priority::High];
+        for (i, &priority) in priorities.iter().enumerate() {
+            let log = log.clone();
+            pool.execute_with_priority(priority, move || log.lock().unwrap().push(i));
+        }
+
+        release.send(()).unwrap();
+        pool.join();
+        assert_eq!(*log.lock().unwrap(), vec![2, 4, 1, 0, 3]);
+    }
+
+    #[test]
+    fn test_priority_aging() {
+        let (mut pool, release) = testing::blocked_pool();
+        pool.set_aging_interval(Duration::from_millis(20));
+        let log = Arc::new(Mutex::new(Vec::new()));
+        {
+            let log = log.clone();
+            pool.execute_with_priority(Priority::Low, move || log.lock().unwrap().push(0));
+        }
+        // Waiting three intervals lifts the low priority job above a new high
+        // priority one.
+        sleep(Duration::from_millis(80));
+        {
+            let log = log.clone();
+            pool.execute_with_priority(Priority::High, move || log.lock().unwrap().push(1));
+        }
+
+        release.send(()).unwrap();
+        pool.join();
+        assert_eq!(*log.lock().unwrap(), vec![0, 1]);
+    }
+}
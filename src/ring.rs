@@ -0,0 +1,257 @@
+// Copyright 2014 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! A bounded lock-free job queue.
+
+use std::cell::UnsafeCell;
+use std::fmt;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use super::{JobQueue, PendingJob, Priority};
+
+struct Slot {
+    // The position this slot is ready for: equal to the position if it can be
+    // written, one past it if it can be read.
+    sequence: AtomicUsize,
+    job: UnsafeCell<Option<PendingJob>>,
+}
+
+/// A bounded multi-producer multi-consumer queue backed by a ring buffer.
+///
+/// Jobs are pushed and popped with atomic operations on the positions of the
+/// ring, without taking a lock, which keeps contention low when many threads
+/// submit small jobs. The queue runs jobs in submission order and ignores
+/// their [`Priority`]. When it is full, the
+/// [rejection policy](enum.RejectionPolicy.html) of the pool applies.
+///
+/// # Example
+///
+/// ```rust
+/// use threadpool::{RingQueue, ThreadPool};
+/// use std::sync::atomic::{AtomicUsize, Ordering};
+/// use std::sync::Arc;
+///
+/// let pool = ThreadPool::with_job_queue(4, RingQueue::new(64));
+/// let counter = Arc::new(AtomicUsize::new(0));
+/// for _ in 0..1000 {
+///     let counter = counter.clone();
+///     pool.execute(move || {
+///         counter.fetch_add(1, Ordering::Relaxed);
+///     });
+/// }
+/// pool.join();
+/// assert_eq!(counter.load(Ordering::Relaxed), 1000);
+/// ```
+///
+/// [`Priority`]: enum.Priority.html
+pub struct RingQueue {
+    slots: Box<[Slot]>,
+    push_pos: AtomicUsize,
+    pop_pos: AtomicUsize,
+}
+
+// Every slot is only accessed by the single thread that claimed its position.
+unsafe impl Sync for RingQueue {}
+
+impl RingQueue {
+    /// Creates a queue that holds at most `capacity` jobs.
+    ///
+    /// # Panics
+    ///
+    /// This function will panic if `capacity` is 0.
+    pub fn new(capacity: usize) -> RingQueue {
+        assert!(capacity > 0);
+        RingQueue {
+            slots: (0..capacity)
+                .map(|i| {
+                    Slot {
+                        sequence: AtomicUsize::new(i),
+                        job: UnsafeCell::new(None),
+                    }
+                })
+                .collect(),
+            push_pos: AtomicUsize::new(0),
+            pop_pos: AtomicUsize::new(0),
+        }
+    }
+}
+
+impl JobQueue for RingQueue {
+    fn push(&self, job: PendingJob, _priority: Priority) -> Result<(), PendingJob> {
+        let mut pos = self.push_pos.load(Ordering::Relaxed);
+        loop {
+            let slot = &self.slots[pos % self.slots.len()];
+            let sequence = slot.sequence.load(Ordering::Acquire);
+            let lag = sequence.wrapping_sub(pos) as isize;
+            if lag == 0 {
+                let next = pos.wrapping_add(1);
+                match self.push_pos.compare_exchange_weak(pos, next, Ordering::Relaxed, Ordering::Relaxed) {
+                    Ok(_) => {
+                        unsafe { *slot.job.get() = Some(job) };
+                        slot.sequence.store(next, Ordering::Release);
+                        return Ok(());
+                    }
+                    Err(current) => pos = current,
+                }
+            } else if lag < 0 {
+                // The slot still holds the job from one lap ago.
+                return Err(job);
+            } else {
+                pos = self.push_pos.load(Ordering::Relaxed);
+            }
+        }
+    }
+
+    fn pop(&self) -> Option<PendingJob> {
+        let mut pos = self.pop_pos.load(Ordering::Relaxed);
+        loop {
+            let slot = &self.slots[pos % self.slots.len()];
+            let sequence = slot.sequence.load(Ordering::Acquire);
+            let lag = sequence.wrapping_sub(pos.wrapping_add(1)) as isize;
+            if lag == 0 {
+                let next = pos.wrapping_add(1);
+                match self.pop_pos.compare_exchange_weak(pos, next, Ordering::Relaxed, Ordering::Relaxed) {
+                    Ok(_) => {
+                        let job = unsafe { (*slot.job.get()).take() };
+                        slot.sequence.store(pos.wrapping_add(self.slots.len()), Ordering::Release);
+                        return job;
+                    }
+                    Err(current) => pos = current,
+                }
+            } else if lag < 0 {
+                // Nothing has been written to the slot yet.
+                return None;
+            } else {
+                pos = self.pop_pos.load(Ordering::Relaxed);
+            }
+        }
+    }
+
+    fn capacity(&self) -> Option<usize> {
+        Some(self.slots.len())
+    }
+}
+
+impl fmt::Debug for RingQueue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("RingQueue")
+            .field("capacity", &self.slots.len())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::{testing, Job, JobQueue, PendingJob, PoolError, Priority, RejectionPolicy, ThreadPool,
+                       ThreadPoolBuilder};
+    use super::RingQueue;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::thread;
+
+    fn counting_job(counter: &Arc<AtomicUsize>) -> PendingJob {
+        let counter = counter.clone();
+        PendingJob::new(Job::new(Box::new(move || {
+            counter.fetch_add(1, Ordering::SeqCst);
+        })), Priority::Normal)
+    }
+
+    #[test]
+    fn test_ring_wraps_around() {
+        let queue = RingQueue::new(3);
+        let counter = Arc::new(AtomicUsize::new(0));
+        for _ in 0..10 {
+            for _ in 0..3 {
+                assert!(queue.push(counting_job(&counter), Priority::Normal).is_ok());
+            }
+            assert!(queue.push(counting_job(&counter), Priority::Normal).is_err());
+            while let Some(job) = queue.pop() {
+                job.run();
+            }
+        }
+        assert_eq!(counter.load(Ordering::SeqCst), 30);
+    }
+
+    #[test]
+    fn test_ring_concurrent() {
+        let queue = Arc::new(RingQueue::new(8));
+        let counter = Arc::new(AtomicUsize::new(0));
+        let producers: Vec<_> = (0..4)
+            .map(|_| {
+                let queue = queue.clone();
+                let counter = counter.clone();
+                thread::spawn(move || for _ in 0..1000 {
+                    let mut job = counting_job(&counter);
+                    while let Err(rejected) = queue.push(job, Priority::Normal) {
+                        job = rejected;
+                        thread::yield_now();
+                    }
+                })
+            })
+            .collect();
+        let consumers: Vec<_> = (0..4)
+            .map(|_| {
+                let queue = queue.clone();
+                let counter = counter.clone();
+                thread::spawn(move || while counter.load(Ordering::SeqCst) < 4000 {
+                    match queue.pop() {
+                        Some(job) => job.run(),
+                        None => thread::yield_now(),
+                    }
+                })
+            })
+            .collect();
+        for handle in producers.into_iter().chain(consumers) {
+            handle.join().unwrap();
+        }
+        assert_eq!(counter.load(Ordering::SeqCst), 4000);
+        assert!(queue.pop().is_none());
+    }
+
+    #[test]
+    fn test_builder_job_queue() {
+        let mut pool = ThreadPoolBuilder::new()
+            .thread_name("ring-{}")
+            .num_threads(1)
+            .job_queue(RingQueue::new(4))
+            .queue_capacity(1)
+            .build()
+            .unwrap();
+        pool.set_rejection_policy(RejectionPolicy::Abort);
+        let release = testing::block(&pool);
+
+        assert_eq!(pool.queue_capacity(), Some(1));
+        assert!(pool.try_execute(|| {}).is_ok());
+        match pool.try_execute(|| {}) {
+            Err(PoolError::QueueFull) => {}
+            _ => panic!("the queue should be full"),
+        }
+        release.send(()).unwrap();
+        pool.join();
+        assert_eq!(pool.completed_count(), 2);
+    }
+
+    #[test]
+    fn test_ring_pool_rejects_when_full() {
+        let mut pool = ThreadPool::with_job_queue(1, RingQueue::new(2));
+        pool.set_rejection_policy(RejectionPolicy::Abort);
+        let release = testing::block(&pool);
+
+        assert!(pool.try_execute(|| {}).is_ok());
+        assert!(pool.try_execute(|| {}).is_ok());
+        match pool.try_execute(|| {}) {
+            Err(PoolError::QueueFull) => {}
+            _ => panic!("the ring should be full"),
+        }
+        release.send(()).unwrap();
+        pool.join();
+        assert_eq!(pool.completed_count(), 3);
+    }
+}
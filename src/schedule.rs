@@ -0,0 +1,537 @@
+// Copyright 2014 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Delayed and periodic jobs.
+//!
+//! Every pool lazily starts a single timer thread the first time a job is
+//! scheduled on it. The timer thread only hands due jobs over to the job queue;
+//! the jobs themselves always run on the workers of the pool. The timer thread
+//! never waits for room in a full queue and never runs a job itself, whatever
+//! the rejection policy of the pool.
+
+use std::cmp;
+use std::collections::BinaryHeap;
+use std::io;
+use std::mem;
+use std::sync::{Arc, Condvar, Mutex, Weak};
+use std::thread::Builder;
+use std::time::{Duration, Instant};
+
+use super::{execute_in_pool, index, CancellationToken, Job, PoolError, Priority, ThreadPool,
+            ThreadPoolSharedData, Thunk};
+
+/// A handle to a job scheduled with [`ThreadPool::execute_after`] or one of
+/// its siblings, used to cancel runs of the job that have not started yet.
+///
+/// Dropping the handle does not cancel the job.
+///
+/// [`ThreadPool::execute_after`]: struct.ThreadPool.html#method.execute_after
+#[derive(Clone, Debug)]
+pub struct ScheduleHandle {
+    token: CancellationToken,
+}
+
+impl ScheduleHandle {
+    /// Cancels all future runs of the job. A run that has already started is
+    /// not interrupted.
+    pub fn cancel(&self) {
+        self.token.cancel();
+    }
+
+    /// Returns `true` if the job has been cancelled.
+    pub fn is_cancelled(&self) -> bool {
+        self.token.is_cancelled()
+    }
+
+    /// Returns the cancellation token of the job, which a run of the job can
+    /// poll to stop early.
+    pub fn token(&self) -> &CancellationToken {
+        &self.token
+    }
+}
+
+type PeriodicJob = Arc<dyn Fn() + Send + Sync>;
+
+enum Task {
+    Once(Thunk<'static>),
+    // Runs are scheduled `period` after the previous one was due.
+    FixedRate(PeriodicJob, Duration),
+    // Runs are scheduled `delay` after the previous one finished.
+    FixedDelay(PeriodicJob, Duration),
+}
+
+struct Entry {
+    due: Instant,
+    seq: u64,
+    task: Task,
+    token: CancellationToken,
+}
+
+// `BinaryHeap` is a max-heap, so entries are ordered with the earliest due
+// date, then the earliest scheduled, as the greatest.
+impl Ord for Entry {
+    fn cmp(&self, other: &Entry) -> cmp::Ordering {
+        (other.due, other.seq).cmp(&(self.due, self.seq))
+    }
+}
+
+impl PartialOrd for Entry {
+    fn partial_cmp(&self, other: &Entry) -> Option<cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Entry {
+    fn eq(&self, other: &Entry) -> bool {
+        self.cmp(other) == cmp::Ordering::Equal
+    }
+}
+
+impl Eq for Entry {}
+
+struct SchedulerState {
+    entries: BinaryHeap<Entry>,
+    next_seq: u64,
+    timer_started: bool,
+    closed: bool,
+}
+
+/// The jobs of a pool that wait for their due date.
+pub(crate) struct Scheduler {
+    state: Mutex<SchedulerState>,
+    changed: Condvar,
+}
+
+impl Scheduler {
+    pub fn new() -> Scheduler {
+        Scheduler {
+            state: Mutex::new(SchedulerState {
+                entries: BinaryHeap::new(),
+                next_seq: 0,
+                timer_started: false,
+                closed: false,
+            }),
+            changed: Condvar::new(),
+        }
+    }
+
+    fn schedule(shared_data: &Arc<ThreadPoolSharedData>,
+                due: Instant,
+                task: Task,
+                token: CancellationToken)
+                -> Result<(), PoolError> {
+        let scheduler = &shared_data.scheduler;
+        let mut state = scheduler.state.lock().unwrap();
+        if state.closed {
+            return Err(PoolError::ShutDown);
+        }
+        if !state.timer_started {
+            spawn_timer(shared_data).map_err(PoolError::SpawnFailed)?;
+            state.timer_started = true;
+        }
+        let seq = state.next_seq;
+        state.next_seq += 1;
+        state.entries.push(Entry {
+            due,
+            seq,
+            task,
+            token,
+        });
+        scheduler.changed.notify_one();
+        Ok(())
+    }
+
+    /// Discards all jobs that are not due yet and stops the timer thread.
+    pub fn close(&self) {
+        let entries = {
+            let mut state = self.state.lock().unwrap();
+            state.closed = true;
+            mem::take(&mut state.entries)
+        };
+        self.changed.notify_all();
+        drop(entries);
+    }
+}
+
+fn spawn_timer(shared_data: &Arc<ThreadPoolSharedData>) -> io::Result<()> {
+    let mut builder = Builder::new();
+    if let Some(ref name) = shared_data.name {
+        builder = builder.name(index::helper_thread_name(name, "timer"));
+    }
+    let shared_data = shared_data.clone();
+    builder.spawn(move || run_timer(shared_data)).map(|_| ())
+}
+
+fn run_timer(shared_data: Arc<ThreadPoolSharedData>) {
+    loop {
+        let entry = {
+            let scheduler = &shared_data.scheduler;
+            let mut state = scheduler.state.lock().unwrap();
+            loop {
+                if state.closed {
+                    return;
+                }
+                let now = Instant::now();
+                match state.entries.peek().map(|entry| entry.due) {
+                    Some(due) if due <= now => break state.entries.pop().unwrap(),
+                    Some(due) => state = scheduler.changed.wait_timeout(state, due - now).unwrap().0,
+                    None => state = scheduler.changed.wait(state).unwrap(),
+                }
+            }
+        };
+        dispatch(&shared_data, entry);
+    }
+}
+
+/// Hands a due job over to the job queue of the pool.
+fn dispatch(shared_data: &Arc<ThreadPoolSharedData>, entry: Entry) {
+    if entry.token.is_cancelled() {
+        return;
+    }
+    let Entry { due, task, token, .. } = entry;
+    let thunk: Thunk<'static> = match task {
+        Task::Once(job) => job,
+        task => {
+            let run = PeriodicRun {
+                // Avoid a reference cycle through the job queue.
+                shared_data: Arc::downgrade(shared_data),
+                due,
+                task: Some(task),
+                token: token.clone(),
+            };
+            Box::new(move || run.run())
+        }
+    };
+    // If the pool refuses a delayed job, it is lost. A refused run of a
+    // periodic job is skipped, see `PeriodicRun`.
+    let _ = execute_in_pool(shared_data, Job::with_token(thunk, Some(token)), Priority::Normal, false);
+}
+
+/// A run of a periodic job handed over to the job queue.
+///
+/// If the run is dropped without running, because the queue of the pool was
+/// full or the run was discarded to make room for another job, the next run
+/// is still scheduled, so a full queue only skips runs.
+struct PeriodicRun {
+    shared_data: Weak<ThreadPoolSharedData>,
+    due: Instant,
+    task: Option<Task>,
+    token: CancellationToken,
+}
+
+impl PeriodicRun {
+    fn run(mut self) {
+        let task = self.task.take().unwrap();
+        // If the job panics, it is not run again.
+        match task {
+            Task::FixedRate(ref job, _) | Task::FixedDelay(ref job, _) => job(),
+            Task::Once(_) => unreachable!(),
+        }
+        self.schedule_next(task);
+    }
+
+    fn schedule_next(&self, task: Task) {
+        let next_due = match task {
+            Task::FixedRate(_, period) => self.due + period,
+            Task::FixedDelay(_, delay) => Instant::now() + delay,
+            Task::Once(_) => unreachable!(),
+        };
+        if let Some(shared_data) = self.shared_data.upgrade() {
+            let _ = Scheduler::schedule(&shared_data, next_due, task, self.token.clone());
+        }
+    }
+}
+
+impl Drop for PeriodicRun {
+    fn drop(&mut self) {
+        if let Some(task) = self.task.take() {
+            if !self.token.is_cancelled() {
+                self.schedule_next(task);
+            }
+        }
+    }
+}
+
+impl ThreadPool {
+    /// Executes the function `job` on a thread in the pool once `delay` has
+    /// elapsed.
+    ///
+    /// Jobs that are not due yet when the pool is shut down are discarded, and
+    /// so is a job that is due while the queue of the pool is full, whatever
+    /// the rejection policy of the pool.
+    ///
+    /// # Panics
+    ///
+    /// This function will panic if the pool has been shut down or the timer
+    /// thread of the pool could not be spawned.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use threadpool::ThreadPool;
+    /// use std::sync::mpsc::channel;
+    /// use std::time::{Duration, Instant};
+    ///
+    /// let pool = ThreadPool::new(2);
+    /// let (tx, rx) = channel();
+    /// let start = Instant::now();
+    /// pool.execute_after(Duration::from_millis(50), move || {
+    ///     tx.send(start.elapsed()).unwrap();
+    /// });
+    ///
+    /// assert!(rx.recv().unwrap() >= Duration::from_millis(50));
+    /// ```
+    pub fn execute_after<F>(&self, delay: Duration, job: F) -> ScheduleHandle
+        where F: FnOnce() + Send + 'static
+    {
+        self.execute_at(Instant::now() + delay, job)
+    }
+
+    /// Executes the function `job` on a thread in the pool once `instant` has
+    /// been reached.
+    ///
+    /// # Panics
+    ///
+    /// This function will panic under the same conditions as `execute_after`.
+    pub fn execute_at<F>(&self, instant: Instant, job: F) -> ScheduleHandle
+        where F: FnOnce() + Send + 'static
+    {
+        self.schedule(instant, Task::Once(Box::new(job)))
+    }
+
+    /// Executes the function `job` on a thread in the pool periodically, first
+    /// after `initial_delay` and then every `period`.
+    ///
+    /// Runs never overlap: if a run takes longer than `period`, the next one
+    /// starts late. If a run panics, the job is not run again. A run that the
+    /// pool refuses because its queue is full is skipped.
+    ///
+    /// # Panics
+    ///
+    /// This function will panic if `period` is 0, and under the same
+    /// conditions as `execute_after`.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use threadpool::ThreadPool;
+    /// use std::sync::Mutex;
+    /// use std::sync::mpsc::channel;
+    /// use std::time::Duration;
+    ///
+    /// let pool = ThreadPool::new(2);
+    /// let (tx, rx) = channel();
+    /// let tx = Mutex::new(tx);
+    /// let handle = pool.schedule_at_fixed_rate(Duration::from_millis(0),
+    ///                                          Duration::from_millis(10),
+    ///                                          move || {
+    ///     let _ = tx.lock().unwrap().send(());
+    /// });
+    ///
+    /// rx.iter().take(3).count();
+    /// handle.cancel();
+    /// ```
+    pub fn schedule_at_fixed_rate<F>(&self,
+                                     initial_delay: Duration,
+                                     period: Duration,
+                                     job: F)
+                                     -> ScheduleHandle
+        where F: Fn() + Send + Sync + 'static
+    {
+        assert!(period > Duration::from_secs(0));
+        self.schedule(Instant::now() + initial_delay,
+                      Task::FixedRate(Arc::new(job), period))
+    }
+
+    /// Executes the function `job` on a thread in the pool repeatedly, first
+    /// after `initial_delay` and then `delay` after the previous run finished.
+    ///
+    /// If a run panics, the job is not run again. A run that the pool refuses
+    /// because its queue is full is skipped.
+    ///
+    /// # Panics
+    ///
+    /// This function will panic if `delay` is 0, and under the same conditions
+    /// as `execute_after`.
+    pub fn schedule_with_fixed_delay<F>(&self,
+                                        initial_delay: Duration,
+                                        delay: Duration,
+                                        job: F)
+                                        -> ScheduleHandle
+        where F: Fn() + Send + Sync + 'static
+    {
+        assert!(delay > Duration::from_secs(0));
+        self.schedule(Instant::now() + initial_delay,
+                      Task::FixedDelay(Arc::new(job), delay))
+    }
+
+    fn schedule(&self, due: Instant, task: Task) -> ScheduleHandle {
+        let token = CancellationToken::new();
+        Scheduler::schedule(&self.shared_data, due, task, token.clone()).unwrap();
+        ScheduleHandle { token }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::{testing, RejectionPolicy, ThreadPool};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::mpsc::{channel, RecvTimeoutError};
+    use std::sync::{Arc, Mutex};
+    use std::thread::sleep;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn test_execute_after_order() {
+        let pool = ThreadPool::new(2);
+        let (tx, rx) = channel();
+        for &delay in &[60, 20, 40] {
+            let tx = tx.clone();
+            pool.execute_after(Duration::from_millis(delay), move || tx.send(delay).unwrap());
+        }
+
+        let order: Vec<_> = rx.iter().take(3).collect();
+        assert_eq!(order, vec![20, 40, 60]);
+    }
+
+    #[test]
+    fn test_execute_at_cancel() {
+        let pool = ThreadPool::new(1);
+        let ran = Arc::new(AtomicUsize::new(0));
+        let handle = {
+            let ran = ran.clone();
+            pool.execute_at(Instant::now() + Duration::from_millis(50), move || {
+                ran.fetch_add(1, Ordering::SeqCst);
+            })
+        };
+        handle.cancel();
+        assert!(handle.is_cancelled());
+
+        sleep(Duration::from_millis(150));
+        assert_eq!(ran.load(Ordering::SeqCst), 0);
+    }
+
+    #[test]
+    fn test_fixed_rate_until_cancelled() {
+        let pool = ThreadPool::new(2);
+        let (tx, rx) = channel();
+        let tx = Mutex::new(tx);
+        let handle = pool.schedule_at_fixed_rate(Duration::from_millis(0),
+                                                 Duration::from_millis(10),
+                                                 move || {
+            let _ = tx.lock().unwrap().send(());
+        });
+
+        assert_eq!(rx.iter().take(5).count(), 5);
+        handle.cancel();
+        // Drain a run that may have been in flight while cancelling.
+        sleep(Duration::from_millis(50));
+        while rx.try_recv().is_ok() {}
+        assert!(rx.recv_timeout(Duration::from_millis(100)).is_err());
+    }
+
+    #[test]
+    fn test_fixed_delay_does_not_overlap() {
+        let pool = ThreadPool::new(4);
+        let running = Arc::new(AtomicUsize::new(0));
+        let runs = Arc::new(AtomicUsize::new(0));
+        let handle = {
+            let (running, runs) = (running.clone(), runs.clone());
+            pool.schedule_with_fixed_delay(Duration::from_millis(0),
+                                           Duration::from_millis(1),
+                                           move || {
+                assert_eq!(running.fetch_add(1, Ordering::SeqCst), 0);
+                sleep(Duration::from_millis(5));
+                running.fetch_sub(1, Ordering::SeqCst);
+                runs.fetch_add(1, Ordering::SeqCst);
+            })
+        };
+
+        sleep(Duration::from_millis(100));
+        handle.cancel();
+        pool.join();
+        assert!(runs.load(Ordering::SeqCst) >= 2);
+        assert_eq!(pool.panic_count(), 0);
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_zero_period_panics() {
+        let pool = ThreadPool::new(1);
+        pool.schedule_at_fixed_rate(Duration::from_millis(0), Duration::from_millis(0), || {});
+    }
+
+    #[test]
+    fn test_due_job_never_runs_on_timer_thread() {
+        for &policy in &[RejectionPolicy::Block, RejectionPolicy::CallerRuns] {
+            let (mut pool, release) = testing::blocked_pool();
+            pool.set_queue_capacity(Some(1));
+            pool.set_rejection_policy(policy);
+            pool.execute(|| {});
+
+            let (tx, rx) = channel();
+            pool.execute_after(Duration::from_millis(0), move || tx.send(()).unwrap());
+            // The job is discarded instead of stalling the timer thread or
+            // running on it.
+            assert_eq!(rx.recv_timeout(Duration::from_secs(10)), Err(RecvTimeoutError::Disconnected));
+            drop(release);
+            pool.join();
+        }
+    }
+
+    #[test]
+    fn test_fixed_rate_survives_full_queue() {
+        let mut pool = ThreadPool::new(1);
+        pool.set_queue_capacity(Some(1));
+        pool.set_rejection_policy(RejectionPolicy::Abort);
+        let release = testing::block(&pool);
+        pool.execute(|| {});
+
+        let runs = Arc::new(AtomicUsize::new(0));
+        let handle = {
+            let runs = runs.clone();
+            pool.schedule_at_fixed_rate(Duration::from_millis(0),
+                                        Duration::from_millis(10),
+                                        move || {
+                runs.fetch_add(1, Ordering::SeqCst);
+            })
+        };
+        // Every run is refused while the queue is full.
+        sleep(Duration::from_millis(50));
+        assert_eq!(runs.load(Ordering::SeqCst), 0);
+
+        drop(release);
+        for _ in 0..100 {
+            if runs.load(Ordering::SeqCst) >= 2 {
+                break;
+            }
+            sleep(Duration::from_millis(10));
+        }
+        handle.cancel();
+        assert!(runs.load(Ordering::SeqCst) >= 2);
+    }
+
+    #[test]
+    fn test_shutdown_discards_scheduled_jobs() {
+        let pool = ThreadPool::new(1);
+        let ran = Arc::new(AtomicUsize::new(0));
+        {
+            let ran = ran.clone();
+            pool.execute_after(Duration::from_millis(50), move || {
+                ran.fetch_add(1, Ordering::SeqCst);
+            });
+        }
+        pool.shutdown();
+        assert!(pool.await_termination(Duration::from_secs(10)));
+
+        sleep(Duration::from_millis(100));
+        assert_eq!(ran.load(Ordering::SeqCst), 0);
+    }
+}
@@ -0,0 +1,297 @@
+// Copyright 2014 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Scoped jobs that may borrow from the stack of the thread creating the scope.
+
+use std::any::Any;
+use std::cell::Cell;
+use std::marker::PhantomData;
+use std::mem;
+use std::panic;
+use std::sync::{Arc, Condvar, Mutex};
+
+use super::{handle, Job, Priority, ThreadPool, Thunk};
+
+struct ScopeState {
+    pending: Mutex<usize>,
+    done: Condvar,
+    panic: Mutex<Option<Box<dyn Any + Send>>>,
+}
+
+impl ScopeState {
+    /// Records the panic of a job, unless an earlier job already panicked.
+    fn set_panic(&self, payload: Box<dyn Any + Send>) {
+        let mut panic = self.panic.lock().unwrap();
+        if panic.is_none() {
+            *panic = Some(payload);
+        }
+    }
+
+    fn wait(&self) {
+        let mut pending = self.pending.lock().unwrap();
+        while *pending > 0 {
+            pending = self.done.wait(pending).unwrap();
+        }
+    }
+}
+
+/// A job of a scope together with the bookkeeping that keeps the scope open.
+///
+/// The job is always dropped before the scope is told that it finished, so no
+/// borrow of the job can outlive the scope, even if the job never runs. A job
+/// that is dropped without running, because the queue discarded it or the
+/// pool was shut down, makes the scope panic like a job that panicked.
+struct ScopedJob<F> {
+    job: Option<F>,
+    state: Arc<ScopeState>,
+}
+
+impl<F: FnOnce()> ScopedJob<F> {
+    fn run(mut self) {
+        let job = self.job.take().unwrap();
+        handle::run_catching(job, move |result| {
+            if let Err(payload) = result {
+                self.state.set_panic(payload);
+            }
+            drop(self);
+        });
+    }
+}
+
+impl<F> Drop for ScopedJob<F> {
+    fn drop(&mut self) {
+        if let Some(job) = self.job.take() {
+            drop(job);
+            self.state.set_panic(handle::lost());
+        }
+        let mut pending = self.state.pending.lock().unwrap();
+        *pending -= 1;
+        if *pending == 0 {
+            self.state.done.notify_all();
+        }
+    }
+}
+
+/// Blocks until every job of the scope has finished, even if the scope body
+/// unwinds.
+struct ScopeGuard<'a>(&'a ScopeState);
+
+impl<'a> Drop for ScopeGuard<'a> {
+    fn drop(&mut self) {
+        self.0.wait();
+    }
+}
+
+/// A scope in which jobs may borrow data that outlives the scope.
+///
+/// Created by [`ThreadPool::scope`].
+///
+/// [`ThreadPool::scope`]: struct.ThreadPool.html#method.scope
+pub struct Scope<'pool, 'scope> {
+    pool: &'pool ThreadPool,
+    state: Arc<ScopeState>,
+    // Make `'scope` invariant so it cannot be shrunk to a borrow that ends
+    // before the scope does.
+    _marker: PhantomData<Cell<&'scope mut ()>>,
+}
+
+impl<'pool, 'scope> Scope<'pool, 'scope> {
+    /// Executes the function `job` on a thread in the pool. `job` may borrow
+    /// anything that outlives the scope.
+    ///
+    /// # Panics
+    ///
+    /// This function will panic if the pool has been shut down.
+    pub fn execute<F>(&self, job: F)
+        where F: FnOnce() + Send + 'scope
+    {
+        *self.state.pending.lock().unwrap() += 1;
+        let job = ScopedJob {
+            job: Some(job),
+            state: self.state.clone(),
+        };
+        let thunk: Thunk<'scope> = Box::new(move || job.run());
+        // The scope does not end before the job has been run or dropped, so
+        // treating its borrows as `'static` is sound.
+        let thunk = unsafe { mem::transmute::<Thunk<'scope>, Thunk<'static>>(thunk) };
+        self.pool.execute_job(Job::new(thunk), Priority::Normal).unwrap();
+    }
+}
+
+impl ThreadPool {
+    /// Creates a scope for executing jobs that borrow from the current stack.
+    ///
+    /// All jobs executed through the [`Scope`] are run on the existing workers
+    /// of the pool, and `scope` does not return before every one of them has
+    /// finished. If any of the jobs panicked, the panic of the first one is
+    /// propagated to the caller once all jobs are done. A job that was dropped
+    /// without running, because a bounded queue discarded it or the pool was
+    /// shut down with [`shutdown_now`], counts as a panicked job.
+    ///
+    /// Calling `scope` from inside a job of the same pool blocks that worker
+    /// while it waits, which may deadlock a pool with a single thread.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use threadpool::ThreadPool;
+    /// use std::sync::atomic::{AtomicUsize, Ordering};
+    ///
+    /// let pool = ThreadPool::new(4);
+    /// let data = vec![1, 2, 3, 4, 5, 6, 7, 8];
+    /// let sum = AtomicUsize::new(0);
+    ///
+    /// pool.scope(|s| {
+    ///     for chunk in data.chunks(2) {
+    ///         let sum = &sum;
+    ///         s.execute(move || {
+    ///             sum.fetch_add(chunk.iter().sum(), Ordering::SeqCst);
+    ///         });
+    ///     }
+    /// });
+    ///
+    /// assert_eq!(sum.load(Ordering::SeqCst), 36);
+    /// ```
+    ///
+    /// [`Scope`]: struct.Scope.html
+    /// [`shutdown_now`]: #method.shutdown_now
+    pub fn scope<'pool, 'scope, F, R>(&'pool self, f: F) -> R
+        where F: FnOnce(&Scope<'pool, 'scope>) -> R
+    {
+        let scope = Scope {
+            pool: self,
+            state: Arc::new(ScopeState {
+                pending: Mutex::new(0),
+                done: Condvar::new(),
+                panic: Mutex::new(None),
+            }),
+            _marker: PhantomData,
+        };
+        let result = {
+            let _guard = ScopeGuard(&scope.state);
+            f(&scope)
+        };
+        if let Some(payload) = scope.state.panic.lock().unwrap().take() {
+            panic::resume_unwind(payload);
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::{testing, RejectionPolicy, ThreadPool};
+    use std::panic::{self, AssertUnwindSafe};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::thread::sleep;
+    use std::time::Duration;
+
+    #[test]
+    fn test_scope_borrows() {
+        let pool = ThreadPool::new(4);
+        let mut results = vec![0; 16];
+
+        pool.scope(|s| {
+            for (i, slot) in results.iter_mut().enumerate() {
+                s.execute(move || {
+                    sleep(Duration::from_millis(5));
+                    *slot = i * i;
+                });
+            }
+        });
+
+        let expected: Vec<_> = (0..16).map(|i| i * i).collect();
+        assert_eq!(results, expected);
+    }
+
+    #[test]
+    fn test_scope_propagates_panic() {
+        let pool = ThreadPool::new(2);
+        let finished = AtomicUsize::new(0);
+
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            pool.scope(|s| {
+                s.execute(|| panic!("scoped boom"));
+                for _ in 0..4 {
+                    s.execute(|| {
+                        sleep(Duration::from_millis(10));
+                        finished.fetch_add(1, Ordering::SeqCst);
+                    });
+                }
+            })
+        }));
+
+        let payload = result.unwrap_err();
+        assert_eq!(payload.downcast_ref::<&str>(), Some(&"scoped boom"));
+        // The scope still waited for the remaining jobs.
+        assert_eq!(finished.load(Ordering::SeqCst), 4);
+    }
+
+    #[test]
+    fn test_scope_returns_value() {
+        let pool = ThreadPool::new(2);
+        let counter = AtomicUsize::new(0);
+        let value = pool.scope(|s| {
+            for _ in 0..10 {
+                s.execute(|| {
+                    counter.fetch_add(1, Ordering::SeqCst);
+                });
+            }
+            "scoped"
+        });
+        assert_eq!(value, "scoped");
+        assert_eq!(counter.load(Ordering::SeqCst), 10);
+    }
+
+    #[test]
+    fn test_scope_panics_on_discarded_job() {
+        let (mut pool, release) = testing::blocked_pool();
+        pool.set_queue_capacity(Some(1));
+        pool.set_rejection_policy(RejectionPolicy::DiscardNewest);
+        let ran = AtomicUsize::new(0);
+
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            pool.scope(|s| {
+                for _ in 0..5 {
+                    s.execute(|| {
+                        ran.fetch_add(1, Ordering::SeqCst);
+                    });
+                }
+                release.send(()).unwrap();
+            })
+        }));
+
+        let payload = result.unwrap_err();
+        assert_eq!(payload.downcast_ref::<&str>(), Some(&"job was dropped before it completed"));
+        assert_eq!(ran.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn test_scope_panics_on_drained_job() {
+        let (pool, release) = testing::blocked_pool();
+        let ran = AtomicUsize::new(0);
+
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            pool.scope(|s| {
+                for _ in 0..2 {
+                    s.execute(|| {
+                        ran.fetch_add(1, Ordering::SeqCst);
+                    });
+                }
+                assert_eq!(pool.shutdown_now().len(), 2);
+                release.send(()).unwrap();
+            })
+        }));
+
+        let payload = result.unwrap_err();
+        assert_eq!(payload.downcast_ref::<&str>(), Some(&"job was dropped before it completed"));
+        assert_eq!(ran.load(Ordering::SeqCst), 0);
+    }
+}
@@ -0,0 +1,246 @@
+// Copyright 2014 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Snapshots of the counters of a pool.
+
+use std::sync::atomic::Ordering;
+
+use super::{ThreadPool, ThreadPoolSharedData};
+
+/// A snapshot of the counters of a pool, returned by [`ThreadPool::stats`].
+///
+/// [`ThreadPool::stats`]: struct.ThreadPool.html#method.stats
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct PoolStats {
+    queued: usize,
+    active: usize,
+    threads: usize,
+    submitted: usize,
+    completed: usize,
+    panicked: usize,
+    cancelled: usize,
+    threads_spawned: usize,
+    threads_retired: usize,
+    peak_queued: usize,
+    peak_active: usize,
+}
+
+impl PoolStats {
+    /// Returns the number of jobs waiting to be run.
+    pub fn queued(&self) -> usize {
+        self.queued
+    }
+
+    /// Returns the number of jobs being run.
+    pub fn active(&self) -> usize {
+        self.active
+    }
+
+    /// Returns the number of worker threads.
+    pub fn threads(&self) -> usize {
+        self.threads
+    }
+
+    /// Returns the number of jobs accepted by the pool over its lifetime.
+    /// Jobs run by the caller because the queue was full are not included.
+    pub fn submitted(&self) -> usize {
+        self.submitted
+    }
+
+    /// Returns the number of jobs that ran to completion over the lifetime of
+    /// the pool.
+    pub fn completed(&self) -> usize {
+        self.completed
+    }
+
+    /// Returns the number of jobs that panicked over the lifetime of the pool.
+    pub fn panicked(&self) -> usize {
+        self.panicked
+    }
+
+    /// Returns the number of jobs that were dropped without running because
+    /// they had been cancelled, over the lifetime of the pool.
+    pub fn cancelled(&self) -> usize {
+        self.cancelled
+    }
+
+    /// Returns the number of worker threads spawned over the lifetime of the
+    /// pool, including the replacements of threads whose job panicked.
+    pub fn threads_spawned(&self) -> usize {
+        self.threads_spawned
+    }
+
+    /// Returns the number of worker threads that exited over the lifetime of
+    /// the pool, including threads whose job panicked.
+    pub fn threads_retired(&self) -> usize {
+        self.threads_retired
+    }
+
+    /// Returns the largest number of jobs that waited to be run at the same
+    /// time.
+    pub fn peak_queued(&self) -> usize {
+        self.peak_queued
+    }
+
+    /// Returns the largest number of jobs that were run at the same time.
+    pub fn peak_active(&self) -> usize {
+        self.peak_active
+    }
+}
+
+impl ThreadPool {
+    /// Returns a snapshot of the counters of the pool.
+    ///
+    /// The counters are read in an order that keeps the snapshot consistent
+    /// while jobs keep running: every job counted as completed, panicked or
+    /// cancelled is also counted as submitted, and every thread counted as
+    /// retired is also counted as spawned.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use threadpool::ThreadPool;
+    ///
+    /// let pool = ThreadPool::new(2);
+    /// for _ in 0..10 {
+    ///     pool.execute(|| {});
+    /// }
+    /// pool.join();
+    ///
+    /// let stats = pool.stats();
+    /// assert_eq!(stats.submitted(), 10);
+    /// assert_eq!(stats.completed(), 10);
+    /// assert_eq!(stats.queued(), 0);
+    /// ```
+    pub fn stats(&self) -> PoolStats {
+        snapshot(&self.shared_data)
+    }
+}
+
+/// Takes the snapshot returned by [`ThreadPool::stats`].
+pub(crate) fn snapshot(shared_data: &ThreadPoolSharedData) -> PoolStats {
+    // Outcomes are counted after submissions and exits after spawns, so
+    // reading the later ones first keeps them from overtaking the others.
+    let completed = shared_data.completed_count.load(Ordering::SeqCst);
+    let panicked = shared_data.panic_count.load(Ordering::SeqCst);
+    let cancelled = shared_data.cancelled_count.load(Ordering::SeqCst);
+    let threads_retired = shared_data.threads_retired.load(Ordering::SeqCst);
+    let active = shared_data.active_count.load(Ordering::SeqCst);
+    let queued = shared_data.queued_count.load(Ordering::SeqCst);
+    let threads = shared_data.spawned_count.load(Ordering::SeqCst);
+    let submitted = shared_data.submitted_count.load(Ordering::SeqCst);
+    let threads_spawned = shared_data.threads_spawned.load(Ordering::SeqCst);
+    PoolStats {
+        queued,
+        active,
+        threads,
+        submitted,
+        completed,
+        panicked,
+        cancelled,
+        threads_spawned,
+        threads_retired,
+        peak_queued: shared_data.peak_queued.load(Ordering::SeqCst).max(queued),
+        peak_active: shared_data.peak_active.load(Ordering::SeqCst).max(active),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::{testing, CancellationToken, JobBuilder, RejectionPolicy, ThreadPool};
+    use std::thread::{self, sleep};
+    use std::time::Duration;
+
+    #[test]
+    fn test_stats_counts_jobs() {
+        let mut pool = ThreadPool::new(2);
+        pool.set_panic_output(false);
+        let first = testing::block(&pool);
+        let second = testing::block(&pool);
+
+        let token = CancellationToken::new();
+        token.cancel();
+        JobBuilder::new().cancellation_token(token).execute(&pool, || {});
+        for _ in 0..5 {
+            pool.execute(|| {});
+        }
+        pool.execute(|| panic!("counted"));
+        drop(first);
+        drop(second);
+        pool.join();
+
+        let stats = pool.stats();
+        assert_eq!(stats.submitted(), 9);
+        assert_eq!(stats.completed(), 7);
+        assert_eq!(stats.panicked(), 1);
+        assert_eq!(stats.cancelled(), 1);
+        assert_eq!(stats.queued(), 0);
+        assert_eq!(stats.active(), 0);
+        assert_eq!(stats.peak_active(), 2);
+        assert!(stats.peak_queued() >= 1);
+        assert_eq!(stats.threads(), 2);
+        assert_eq!(stats.threads_retired(), 1);
+
+        // The replacement of the thread whose job panicked may still be
+        // starting.
+        for _ in 0..100 {
+            if pool.stats().threads_spawned() == 3 {
+                break;
+            }
+            sleep(Duration::from_millis(10));
+        }
+        assert_eq!(pool.stats().threads_spawned(), 3);
+    }
+
+    #[test]
+    fn test_stats_outcomes_never_exceed_submissions() {
+        let pool = ThreadPool::new(2);
+        let submitter = {
+            let pool = pool.clone();
+            thread::spawn(move || for _ in 0..10_000 {
+                pool.execute(|| {});
+            })
+        };
+        while !submitter.is_finished() {
+            let stats = pool.stats();
+            assert!(stats.completed() + stats.panicked() + stats.cancelled() <= stats.submitted());
+        }
+        submitter.join().unwrap();
+        pool.join();
+        assert_eq!(pool.stats().completed(), 10_000);
+    }
+
+    #[test]
+    fn test_stats_skips_rejected_jobs() {
+        let (mut pool, release) = testing::blocked_pool();
+        pool.set_queue_capacity(Some(1));
+        pool.set_rejection_policy(RejectionPolicy::Abort);
+        pool.execute(|| {});
+        assert!(pool.try_execute(|| {}).is_err());
+
+        let stats = pool.stats();
+        assert_eq!(stats.submitted(), 2);
+        assert_eq!(stats.queued(), 1);
+        release.send(()).unwrap();
+        pool.join();
+        assert_eq!(pool.stats().completed(), 2);
+    }
+
+    #[test]
+    fn test_stats_counts_retired_threads() {
+        let pool = ThreadPool::new(3);
+        pool.shutdown();
+        assert!(pool.await_termination(Duration::from_secs(10)));
+        let stats = pool.stats();
+        assert_eq!(stats.threads(), 0);
+        assert_eq!(stats.threads_spawned(), 3);
+        assert_eq!(stats.threads_retired(), 3);
+    }
+}
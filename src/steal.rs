@@ -0,0 +1,409 @@
+// Copyright 2014 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Per-worker job queues and work stealing.
+//!
+//! Jobs submitted from outside the pool go to the shared job queue, which acts
+//! as the global injector. Jobs submitted by a job that runs on a worker are
+//! pushed to the local queue of that worker instead, without touching the
+//! shared queue. A worker that takes a job from the injector also moves a
+//! batch of the jobs after it to its local queue, so that it takes the lock of
+//! the shared queue once per batch instead of once per job.
+//!
+//! A worker takes jobs from the front of its own queue first, then steals from
+//! the front of the queues of the other workers, and only then takes a new job
+//! from the injector. Jobs in local queues were submitted before the jobs left
+//! in the injector, so they run in the order they were submitted as far as the
+//! workers allow, and jobs batched by a busy worker do not wait for it. While
+//! the injector holds jobs with a priority other than `Normal`, it decides
+//! which job runs next: workers take from it before the local queues, do not
+//! take batches, and jobs submitted from workers go to it too.
+//!
+//! Only `join2` takes jobs from the back of the local queue, to run the job it
+//! has just forked first.
+
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use super::index;
+use super::queue::Popped;
+use super::{Job, Priority, ThreadPoolSharedData};
+
+/// The most jobs a worker moves from the injector to its local queue at once.
+const BATCH_SIZE: usize = 16;
+
+pub(crate) struct LocalQueue {
+    jobs: Mutex<VecDeque<Job>>,
+    // Set once the worker owning the queue has exited while jobs were left in
+    // it. The queue is then removed as soon as it has been emptied.
+    orphaned: AtomicBool,
+}
+
+impl LocalQueue {
+    /// Takes the oldest job, or the newest one if `newest` is `true`.
+    fn pop(&self, queues: &WorkerQueues, newest: bool) -> Option<Job> {
+        let mut jobs = self.jobs.lock().unwrap();
+        let job = if newest { jobs.pop_back() } else { jobs.pop_front() };
+        if job.is_some() {
+            queues.len.fetch_sub(1, Ordering::SeqCst);
+        }
+        job
+    }
+}
+
+/// The local queues of all workers of a pool.
+pub(crate) struct WorkerQueues {
+    locals: Mutex<Vec<Arc<LocalQueue>>>,
+    // Number of jobs in all local queues, only changed with the lock of the
+    // local queue held.
+    len: AtomicUsize,
+    next_victim: AtomicUsize,
+}
+
+impl WorkerQueues {
+    pub fn new() -> WorkerQueues {
+        WorkerQueues {
+            locals: Mutex::new(Vec::new()),
+            len: AtomicUsize::new(0),
+            next_victim: AtomicUsize::new(0),
+        }
+    }
+
+    /// Pushes `job` to the local queue of the current thread if it is a worker
+    /// of the pool, handing it back otherwise.
+    ///
+    /// Local queues are unbounded and ignore priorities, so only jobs with the
+    /// default priority submitted to an unbounded pool whose queue holds no
+    /// job with another priority are pushed locally.
+    pub fn push_local(shared_data: &ThreadPoolSharedData,
+                      job: Job,
+                      priority: Priority)
+                      -> Result<(), Job> {
+        let queue = &shared_data.job_queue;
+        if priority != Priority::Normal || queue.is_bounded() || queue.is_closed() || queue.has_prioritized() {
+            return Err(job);
+        }
+        let local = match index::with_current(shared_data, |worker| worker.local.clone()) {
+            Some(local) => local,
+            None => return Err(job),
+        };
+        {
+            let mut jobs = local.jobs.lock().unwrap();
+            jobs.push_back(job);
+            shared_data.worker_queues.len.fetch_add(1, Ordering::SeqCst);
+        }
+        // Let an idle worker come and steal it.
+        queue.wake_one();
+        Ok(())
+    }
+
+    /// Takes a job for the current thread to run while it waits for another
+    /// job to finish, without blocking, starting with the job it pushed last.
+    /// Returns `None` if there is no job to take or the current thread is not
+    /// a worker of the pool.
+    pub fn try_next_job(shared_data: &ThreadPoolSharedData) -> Option<Job> {
+        let local = index::with_current(shared_data, |worker| worker.local.clone())?;
+        let queues = &shared_data.worker_queues;
+        let injector = &shared_data.job_queue;
+        if injector.has_prioritized() {
+            if let Some(job) = injector.try_pop() {
+                return Some(job);
+            }
+        }
+        local.pop(queues, true)
+            .or_else(|| queues.steal(&local))
+            .or_else(|| injector.try_pop())
+    }
+
+    /// Removes the jobs of all local queues.
+    pub fn drain(&self) -> Vec<Job> {
+        let locals = self.locals.lock().unwrap();
+        let mut drained = Vec::new();
+        for local in locals.iter() {
+            let mut jobs = local.jobs.lock().unwrap();
+            self.len.fetch_sub(jobs.len(), Ordering::SeqCst);
+            drained.extend(jobs.drain(..));
+        }
+        drained
+    }
+
+    /// Takes the oldest job of the local queue of another worker, if any
+    /// local queue holds a job.
+    fn steal(&self, own: &Arc<LocalQueue>) -> Option<Job> {
+        if self.len.load(Ordering::SeqCst) == 0 {
+            return None;
+        }
+        let mut locals = self.locals.lock().unwrap();
+        let start = self.next_victim.fetch_add(1, Ordering::Relaxed);
+        for i in 0..locals.len() {
+            let index = (start + i) % locals.len();
+            if Arc::ptr_eq(&locals[index], own) {
+                continue;
+            }
+            let (job, emptied) = {
+                let local = &locals[index];
+                let mut jobs = local.jobs.lock().unwrap();
+                let job = jobs.pop_front();
+                (job, jobs.is_empty() && local.orphaned.load(Ordering::Relaxed))
+            };
+            if emptied {
+                locals.swap_remove(index);
+            }
+            if let Some(job) = job {
+                self.len.fetch_sub(1, Ordering::SeqCst);
+                return Some(job);
+            }
+        }
+        None
+    }
+}
+
+/// The current thread, registered as the worker of a pool with an index.
+///
+/// Jobs left in the local queue when the worker is dropped, also while
+/// unwinding from a panicking job, stay there until other workers steal them.
+pub(crate) struct Worker<'a> {
+    shared_data: &'a ThreadPoolSharedData,
+    local: Arc<LocalQueue>,
+}
+
+impl<'a> Worker<'a> {
+    pub fn register(shared_data: &'a ThreadPoolSharedData, index: usize) -> Worker<'a> {
+        let local = Arc::new(LocalQueue {
+            jobs: Mutex::new(VecDeque::new()),
+            orphaned: AtomicBool::new(false),
+        });
+        shared_data.worker_queues.locals.lock().unwrap().push(local.clone());
+        index::set_current(shared_data, index, local.clone());
+        Worker { shared_data, local }
+    }
+
+    pub fn has_local_jobs(&self) -> bool {
+        !self.local.jobs.lock().unwrap().is_empty()
+    }
+
+    /// Takes the next job to run, blocking until one is available or the
+    /// duration returned by `keep_alive` has elapsed since the call. Returns
+    /// `None` on timeout, and once the pool is shut down and no jobs are left
+    /// to take.
+    ///
+    /// `keep_alive` is called again whenever the worker is woken up, so that a
+    /// changed duration applies to a worker that is already waiting.
+    pub fn next_job<F>(&self, keep_alive: F) -> Option<Job>
+        where F: Fn() -> Option<Duration>
+    {
+        let queues = &self.shared_data.worker_queues;
+        let injector = &self.shared_data.job_queue;
+        let idle_since = Instant::now();
+        loop {
+            let timeout = keep_alive();
+            let deadline = timeout.map(|timeout| idle_since + timeout);
+            if !injector.has_prioritized() {
+                if let Some(job) = self.local.pop(queues, false).or_else(|| queues.steal(&self.local)) {
+                    return Some(job);
+                }
+            }
+            let interrupt = || queues.len.load(Ordering::SeqCst) > 0 || keep_alive() != timeout;
+            match injector.pop(interrupt, deadline) {
+                Popped::Job(job) => {
+                    self.take_batch();
+                    return Some(job);
+                }
+                Popped::Interrupted => {
+                    if let Some(job) = self.local.pop(queues, false).or_else(|| queues.steal(&self.local)) {
+                        return Some(job);
+                    }
+                }
+                Popped::Closed | Popped::TimedOut => return None,
+            }
+        }
+    }
+
+    /// Moves a batch of jobs from the injector to the local queue, unless the
+    /// local queue could change the order of prioritized jobs or take jobs
+    /// out of a bounded queue.
+    fn take_batch(&self) {
+        let injector = &self.shared_data.job_queue;
+        if injector.has_prioritized() || injector.is_bounded() {
+            return;
+        }
+        let batch = injector.try_pop_batch(BATCH_SIZE);
+        let count = batch.len();
+        if count > 0 {
+            {
+                let mut jobs = self.local.jobs.lock().unwrap();
+                self.shared_data.worker_queues.len.fetch_add(count, Ordering::SeqCst);
+                jobs.extend(batch);
+            }
+            // Let idle workers come and steal them while this one is busy.
+            injector.wake(count);
+        }
+    }
+}
+
+impl<'a> Drop for Worker<'a> {
+    fn drop(&mut self) {
+        index::clear_current();
+        let mut locals = self.shared_data.worker_queues.locals.lock().unwrap();
+        if self.local.jobs.lock().unwrap().is_empty() {
+            locals.retain(|local| !Arc::ptr_eq(local, &self.local));
+        } else {
+            self.local.orphaned.store(true, Ordering::Relaxed);
+            // Let an idle worker come and steal the jobs left behind.
+            self.shared_data.job_queue.wake_one();
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::{testing, Priority, ThreadPool};
+    use std::collections::HashSet;
+    use std::sync::atomic::Ordering;
+    use std::sync::mpsc::channel;
+    use std::sync::{Arc, Mutex};
+    use std::thread::{self, sleep};
+    use std::time::Duration;
+
+    #[test]
+    fn test_nested_jobs_are_stolen() {
+        let pool = ThreadPool::new_with_name("steal".into(), 4);
+        let threads = Arc::new(Mutex::new(HashSet::new()));
+        {
+            let inner_pool = pool.clone();
+            let threads = threads.clone();
+            pool.execute(move || for _ in 0..64 {
+                let threads = threads.clone();
+                inner_pool.execute(move || {
+                    sleep(Duration::from_millis(2));
+                    threads.lock().unwrap().insert(thread::current().id());
+                });
+            });
+        }
+        pool.join();
+
+        assert!(threads.lock().unwrap().len() > 1);
+        assert_eq!(pool.completed_count(), 65);
+    }
+
+    #[test]
+    fn test_nested_jobs_run_in_order() {
+        let pool = ThreadPool::new(1);
+        let (tx, rx) = channel();
+        {
+            let inner_pool = pool.clone();
+            pool.execute(move || for i in 0..5 {
+                let tx = tx.clone();
+                inner_pool.execute(move || tx.send(i).unwrap());
+            });
+        }
+
+        assert_eq!(rx.iter().take(5).collect::<Vec<_>>(), vec![0, 1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn test_external_jobs_run_in_order() {
+        let (pool, release) = testing::blocked_pool();
+        let (tx, rx) = channel();
+        for i in 0..50 {
+            let tx = tx.clone();
+            pool.execute(move || tx.send(i).unwrap());
+        }
+        release.send(()).unwrap();
+
+        assert_eq!(rx.iter().take(50).collect::<Vec<_>>(), (0..50).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_worker_takes_batch() {
+        let (pool, release) = testing::blocked_pool();
+        let (started_tx, started_rx) = channel();
+        let (batch_release_tx, batch_release_rx) = channel::<()>();
+        pool.execute(move || {
+            started_tx.send(()).unwrap();
+            let _ = batch_release_rx.recv();
+        });
+        for _ in 0..10 {
+            pool.execute(|| ());
+        }
+        release.send(()).unwrap();
+        started_rx.recv().unwrap();
+
+        // Half of the jobs left in the injector moved to the local queue.
+        let queues = &pool.shared_data.worker_queues;
+        assert_eq!(queues.len.load(Ordering::SeqCst), 5);
+
+        batch_release_tx.send(()).unwrap();
+        pool.join();
+        assert_eq!(pool.completed_count(), 12);
+    }
+
+    #[test]
+    fn test_prioritized_job_runs_before_nested_jobs() {
+        let pool = ThreadPool::new(1);
+        let (queued_tx, queued_rx) = channel::<()>();
+        let (tx, rx) = channel();
+        {
+            let inner_pool = pool.clone();
+            let tx = tx.clone();
+            pool.execute(move || {
+                queued_rx.recv().unwrap();
+                for i in 0..5 {
+                    let tx = tx.clone();
+                    inner_pool.execute(move || tx.send(i).unwrap());
+                }
+            });
+        }
+        pool.execute_with_priority(Priority::High, move || tx.send(100).unwrap());
+        queued_tx.send(()).unwrap();
+
+        assert_eq!(rx.iter().take(6).collect::<Vec<_>>(), vec![100, 0, 1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn test_shutdown_now_drains_local_jobs() {
+        let pool = ThreadPool::new(1);
+        let inner_pool = pool.clone();
+        let release = testing::block_after(&pool, move || {
+            for _ in 0..5 {
+                inner_pool.execute(|| panic!("never runs"));
+            }
+        });
+
+        assert_eq!(pool.shutdown_now().len(), 5);
+        release.send(()).unwrap();
+        assert!(pool.await_termination(Duration::from_secs(10)));
+        assert_eq!(pool.panic_count(), 0);
+    }
+
+    #[test]
+    fn test_local_jobs_survive_panic() {
+        let pool = ThreadPool::new(1);
+        let (tx, rx) = channel();
+        {
+            let inner_pool = pool.clone();
+            pool.execute(move || {
+                for i in 0..3 {
+                    let tx = tx.clone();
+                    inner_pool.execute(move || tx.send(i).unwrap());
+                }
+                panic!("leaving local jobs behind");
+            });
+        }
+
+        let mut received: Vec<i32> = rx.iter().take(3).collect();
+        received.sort();
+        assert_eq!(received, vec![0, 1, 2]);
+        pool.join();
+        assert_eq!(pool.panic_count(), 1);
+    }
+}
@@ -0,0 +1,45 @@
+// Copyright 2014 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Fixtures shared by the tests of the other modules.
+
+use std::sync::mpsc::{channel, Sender};
+
+use super::ThreadPool;
+
+/// Keeps one worker of `pool` busy until the returned sender is used or
+/// dropped. Returns once the worker is running the blocking job.
+pub fn block(pool: &ThreadPool) -> Sender<()> {
+    block_after(pool, || {})
+}
+
+/// Like [`block`], but the worker first runs `setup`, for example to submit
+/// jobs from inside the pool.
+pub fn block_after<F>(pool: &ThreadPool, setup: F) -> Sender<()>
+    where F: FnOnce() + Send + 'static
+{
+    let (started_tx, started_rx) = channel();
+    let (release_tx, release_rx) = channel::<()>();
+    pool.execute(move || {
+        setup();
+        started_tx.send(()).unwrap();
+        let _ = release_rx.recv();
+    });
+    started_rx.recv().unwrap();
+    release_tx
+}
+
+/// Creates a pool with one thread that is kept busy until the returned sender
+/// is used or dropped.
+pub fn blocked_pool() -> (ThreadPool, Sender<()>) {
+    let pool = ThreadPool::new(1);
+    let release = block(&pool);
+    (pool, release)
+}
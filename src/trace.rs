@@ -0,0 +1,283 @@
+// Copyright 2014 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Events for the lifecycle of worker threads and spans around jobs, emitted
+//! through `tracing` with the `tracing` feature. Without the feature, every
+//! function in this module does nothing.
+//!
+//! All events have the target `threadpool` and a `pool` field with the name
+//! of the pool. Events of a worker also have its `worker` index.
+
+#![cfg_attr(not(feature = "tracing"), allow(unused_variables))]
+
+use std::io;
+
+#[cfg(feature = "tracing")]
+use super::index;
+#[cfg(feature = "tracing")]
+use super::ThreadPoolBuilder;
+use super::ThreadPoolSharedData;
+
+/// The tracing configuration of a pool.
+#[cfg(feature = "tracing")]
+pub(crate) struct Tracer {
+    pool: String,
+    job_spans: bool,
+}
+
+#[cfg(feature = "tracing")]
+impl Tracer {
+    pub fn new(name: Option<&str>, job_spans: bool) -> Tracer {
+        Tracer {
+            pool: index::pool_name(name),
+            job_spans,
+        }
+    }
+}
+
+/// Keeps the span of a job entered until it is dropped.
+#[cfg(feature = "tracing")]
+pub(crate) type JobSpan = Option<::tracing::span::EnteredSpan>;
+#[cfg(not(feature = "tracing"))]
+pub(crate) struct JobSpan;
+
+/// A worker thread started, before it runs any job.
+pub(crate) fn worker_started(shared_data: &ThreadPoolSharedData, worker: usize) {
+    #[cfg(feature = "tracing")]
+    ::tracing::debug!(target: "threadpool", pool = %shared_data.tracer.pool, worker, "worker started");
+}
+
+/// A worker thread exited normally, because the pool was shut down or shrunk
+/// or because it was idle.
+pub(crate) fn worker_stopped(shared_data: &ThreadPoolSharedData, worker: usize) {
+    #[cfg(feature = "tracing")]
+    ::tracing::debug!(target: "threadpool", pool = %shared_data.tracer.pool, worker, "worker stopped");
+}
+
+/// A worker thread is unwinding from a panic in a job, and a replacement is
+/// spawned unless `respawn` is `false`.
+pub(crate) fn worker_panicked(shared_data: &ThreadPoolSharedData, worker: usize, respawn: bool) {
+    #[cfg(feature = "tracing")]
+    {
+        if respawn {
+            ::tracing::warn!(target: "threadpool",
+                             pool = %shared_data.tracer.pool,
+                             worker,
+                             "worker exited after a panic, spawning a replacement");
+        } else {
+            ::tracing::warn!(target: "threadpool",
+                             pool = %shared_data.tracer.pool,
+                             worker,
+                             "worker exited after a panic");
+        }
+    }
+}
+
+/// The thread of the worker with index `worker` could not be spawned.
+pub(crate) fn spawn_failed(shared_data: &ThreadPoolSharedData, worker: usize, error: &io::Error) {
+    #[cfg(feature = "tracing")]
+    ::tracing::error!(target: "threadpool",
+                      pool = %shared_data.tracer.pool,
+                      worker,
+                      error = %error,
+                      "failed to spawn worker thread");
+}
+
+/// The maximum number of threads was changed from `from` to `to`.
+pub(crate) fn resized(shared_data: &ThreadPoolSharedData, from: usize, to: usize) {
+    #[cfg(feature = "tracing")]
+    ::tracing::info!(target: "threadpool", pool = %shared_data.tracer.pool, from, to, "pool resized");
+}
+
+/// The pool was shut down, discarding `discarded` queued jobs if it was shut
+/// down immediately.
+pub(crate) fn shut_down(shared_data: &ThreadPoolSharedData, discarded: Option<usize>) {
+    #[cfg(feature = "tracing")]
+    {
+        match discarded {
+            Some(discarded) => {
+                ::tracing::info!(target: "threadpool",
+                                 pool = %shared_data.tracer.pool,
+                                 discarded,
+                                 "pool shut down immediately")
+            }
+            None => ::tracing::info!(target: "threadpool", pool = %shared_data.tracer.pool, "pool shut down"),
+        }
+    }
+}
+
+/// A job panicked on the worker with index `worker`, or outside the workers of
+/// the pool if `worker` is `None`.
+pub(crate) fn job_panicked(shared_data: &ThreadPoolSharedData,
+                           worker: Option<usize>,
+                           job: Option<&str>,
+                           message: Option<&str>) {
+    #[cfg(feature = "tracing")]
+    ::tracing::error!(target: "threadpool",
+                      pool = %shared_data.tracer.pool,
+                      worker,
+                      job,
+                      message,
+                      "job panicked");
+}
+
+/// Enters the span of a job run by the worker with index `worker`, if the pool
+/// was built with [`job_spans`](struct.ThreadPoolBuilder.html#method.job_spans).
+/// The span has no `worker` field if `worker` is `None`.
+#[cfg(feature = "tracing")]
+pub(crate) fn job_span(shared_data: &ThreadPoolSharedData, worker: Option<usize>, job: Option<&str>) -> JobSpan {
+    if shared_data.tracer.job_spans {
+        Some(::tracing::debug_span!(target: "threadpool", "job", pool = %shared_data.tracer.pool, worker, job)
+            .entered())
+    } else {
+        None
+    }
+}
+
+#[cfg(not(feature = "tracing"))]
+pub(crate) fn job_span(shared_data: &ThreadPoolSharedData, worker: Option<usize>, job: Option<&str>) -> JobSpan {
+    JobSpan
+}
+
+#[cfg(feature = "tracing")]
+impl ThreadPoolBuilder {
+    /// Runs every job inside a `job` span at the debug level, with the name of
+    /// the pool, the index of the worker and the name of the job, if any, as
+    /// its `pool`, `worker` and `job` fields. Defaults to `false`.
+    ///
+    /// Events the job emits are recorded within the span, which ties them to
+    /// the pool and worker that ran the job. Only available with the `tracing`
+    /// feature.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// # extern crate threadpool;
+    /// # extern crate tracing;
+    /// use threadpool::ThreadPoolBuilder;
+    ///
+    /// # fn main() {
+    /// let pool = ThreadPoolBuilder::new()
+    ///     .thread_name("ingest-{}")
+    ///     .job_spans(true)
+    ///     .build()
+    ///     .unwrap();
+    /// pool.execute(|| tracing::info!("parsed record"));
+    /// pool.join();
+    /// # }
+    /// ```
+    pub fn job_spans(mut self, enabled: bool) -> ThreadPoolBuilder {
+        self.job_spans = enabled;
+        self
+    }
+}
+
+#[cfg(all(test, feature = "tracing"))]
+mod test {
+    use super::super::ThreadPoolBuilder;
+    use std::fmt;
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+    use tracing::field::{Field, Visit};
+    use tracing::span::{Attributes, Id, Record};
+    use tracing::{Event, Metadata, Subscriber};
+
+    /// The fields of an event or span as `name=value` strings.
+    #[derive(Default)]
+    struct Fields(Vec<String>);
+
+    impl Visit for Fields {
+        fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+            self.0.push(format!("{}={:?}", field.name(), value));
+        }
+
+        fn record_str(&mut self, field: &Field, value: &str) {
+            self.0.push(format!("{}={}", field.name(), value));
+        }
+    }
+
+    /// Records the events and spans of the pool named `traced`.
+    struct Recorder {
+        next_id: AtomicU64,
+        records: Mutex<Vec<Vec<String>>>,
+    }
+
+    impl Recorder {
+        fn push(&self, name: &str, fields: Fields) {
+            if fields.0.iter().any(|field| field == "pool=traced") {
+                let mut record = vec![name.to_owned()];
+                record.extend(fields.0);
+                self.records.lock().unwrap().push(record);
+            }
+        }
+    }
+
+    impl Subscriber for Recorder {
+        fn enabled(&self, metadata: &Metadata) -> bool {
+            metadata.target() == "threadpool"
+        }
+
+        fn new_span(&self, span: &Attributes) -> Id {
+            let mut fields = Fields::default();
+            span.record(&mut fields);
+            self.push(&format!("span {}", span.metadata().name()), fields);
+            Id::from_u64(self.next_id.fetch_add(1, Ordering::SeqCst))
+        }
+
+        fn record(&self, _span: &Id, _values: &Record) {}
+
+        fn record_follows_from(&self, _span: &Id, _follows: &Id) {}
+
+        fn event(&self, event: &Event) {
+            let mut fields = Fields::default();
+            event.record(&mut fields);
+            self.push("event", fields);
+        }
+
+        fn enter(&self, _span: &Id) {}
+
+        fn exit(&self, _span: &Id) {}
+    }
+
+    #[test]
+    fn test_lifecycle_events() {
+        let recorder = Arc::new(Recorder {
+            next_id: AtomicU64::new(1),
+            records: Mutex::new(Vec::new()),
+        });
+        tracing::subscriber::set_global_default(recorder.clone()).unwrap();
+
+        let mut pool = ThreadPoolBuilder::new()
+            .thread_name("traced-{}")
+            .num_threads(1)
+            .job_spans(true)
+            .build()
+            .unwrap();
+        pool.set_panic_output(false);
+        pool.execute(|| panic!("boom"));
+        pool.join();
+        pool.set_num_threads(2);
+        pool.shutdown();
+        assert!(pool.await_termination(Duration::from_secs(10)));
+
+        let records = recorder.records.lock().unwrap();
+        let has = |expected: &[&str]| {
+            records.iter().any(|record| expected.iter().all(|field| record.iter().any(|f| f == field)))
+        };
+        assert!(has(&["event", "message=worker started", "worker=0"]));
+        assert!(has(&["span job", "worker=0"]));
+        assert!(has(&["event", "message=job panicked", "worker=0", "message=boom"]));
+        assert!(has(&["event", "message=worker exited after a panic, spawning a replacement"]));
+        assert!(has(&["event", "message=pool resized", "from=1", "to=2"]));
+        assert!(has(&["event", "message=pool shut down"]));
+        assert!(has(&["event", "message=worker stopped", "worker=0"]));
+    }
+}
@@ -0,0 +1,409 @@
+// Copyright 2014 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Detection of jobs that run longer than their time budget.
+//!
+//! Workers register every job that has a time budget before running it. A
+//! watchdog thread, started when a [`Watchdog`] is installed on a pool, sleeps
+//! until the earliest budget runs out and reports every job that is still
+//! running by then.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::io;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread::{self, Builder};
+use std::time::{Duration, Instant};
+
+use super::{index, spawn_in_pool, Job, PoolError, ThreadPool, ThreadPoolSharedData};
+
+/// A job that has been running for longer than its time budget.
+///
+/// Passed to the callback of a [`Watchdog`](struct.Watchdog.html).
+#[derive(Clone, Debug)]
+pub struct OverdueJob {
+    job_name: Option<String>,
+    thread_name: Option<String>,
+    elapsed: Duration,
+    time_budget: Duration,
+}
+
+impl OverdueJob {
+    /// Returns the name the job was submitted with, if any.
+    pub fn job_name(&self) -> Option<&str> {
+        self.job_name.as_ref().map(|name| &name[..])
+    }
+
+    /// Returns the name of the worker thread running the job, if it has one.
+    pub fn thread_name(&self) -> Option<&str> {
+        self.thread_name.as_ref().map(|name| &name[..])
+    }
+
+    /// Returns how long the job had been running when it was reported.
+    pub fn elapsed(&self) -> Duration {
+        self.elapsed
+    }
+
+    /// Returns the time budget the job exceeded.
+    pub fn time_budget(&self) -> Duration {
+        self.time_budget
+    }
+}
+
+type OverdueCallback = Arc<dyn Fn(&OverdueJob) + Send + Sync>;
+
+/// Configuration of the watchdog of a pool, installed with
+/// [`ThreadPool::set_watchdog`].
+///
+/// The watchdog invokes its callback once for every job that runs longer than
+/// its time budget. Jobs get a budget from
+/// [`JobBuilder::time_budget`](struct.JobBuilder.html#method.time_budget), or
+/// from the [default budget](#method.default_time_budget) of the watchdog.
+/// Jobs without a budget are never reported.
+///
+/// # Example
+///
+/// ```rust
+/// use threadpool::{JobBuilder, ThreadPool, Watchdog};
+/// use std::sync::mpsc::channel;
+/// use std::sync::Mutex;
+/// use std::thread::sleep;
+/// use std::time::Duration;
+///
+/// let (tx, rx) = channel();
+/// let tx = Mutex::new(tx);
+/// let mut pool = ThreadPool::new(2);
+/// pool.set_watchdog(Watchdog::new(move |job| {
+///         let name = job.job_name().unwrap_or("unnamed").to_owned();
+///         tx.lock().unwrap().send(name).unwrap();
+///     }))
+///     .unwrap();
+///
+/// JobBuilder::new()
+///     .name("slow")
+///     .time_budget(Duration::from_millis(10))
+///     .execute(&pool, || sleep(Duration::from_millis(100)));
+///
+/// assert_eq!(rx.recv().unwrap(), "slow");
+/// ```
+///
+/// [`ThreadPool::set_watchdog`]: struct.ThreadPool.html#method.set_watchdog
+#[derive(Clone)]
+pub struct Watchdog {
+    callback: OverdueCallback,
+    default_time_budget: Option<Duration>,
+    compensate: bool,
+}
+
+impl Watchdog {
+    /// Creates a watchdog that calls `callback` for every overdue job.
+    ///
+    /// The callback runs on the watchdog thread of the pool, so it should
+    /// return quickly. A panic in the callback is ignored.
+    pub fn new<F>(callback: F) -> Watchdog
+        where F: Fn(&OverdueJob) + Send + Sync + 'static
+    {
+        Watchdog {
+            callback: Arc::new(callback),
+            default_time_budget: None,
+            compensate: false,
+        }
+    }
+
+    /// Sets the time budget of jobs that were submitted without one.
+    pub fn default_time_budget(mut self, budget: Duration) -> Watchdog {
+        self.default_time_budget = Some(budget);
+        self
+    }
+
+    /// Spawns an additional worker for every overdue job, so that the number
+    /// of threads available to other jobs stays the same. Once the overdue
+    /// job has finished, the worker that ran it exits, which brings the pool
+    /// back to its number of threads.
+    pub fn compensate(mut self, compensate: bool) -> Watchdog {
+        self.compensate = compensate;
+        self
+    }
+}
+
+impl fmt::Debug for Watchdog {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Watchdog")
+            .field("default_time_budget", &self.default_time_budget)
+            .field("compensate", &self.compensate)
+            .finish()
+    }
+}
+
+struct RunningJob {
+    job_name: Option<String>,
+    thread_name: Option<String>,
+    started: Instant,
+    time_budget: Duration,
+    overdue: bool,
+    compensated: bool,
+}
+
+impl RunningJob {
+    fn deadline(&self) -> Instant {
+        self.started + self.time_budget
+    }
+}
+
+struct MonitorState {
+    watchdog: Option<Watchdog>,
+    running: HashMap<u64, RunningJob>,
+    next_id: u64,
+    closed: bool,
+}
+
+/// The jobs of a pool that are running with a time budget.
+pub(crate) struct JobMonitor {
+    // Lets workers skip the lock while no watchdog is installed.
+    enabled: AtomicBool,
+    state: Mutex<MonitorState>,
+    changed: Condvar,
+}
+
+/// Unregisters a job from the monitor when it finishes, even if it panics.
+pub(crate) struct WatchGuard<'a> {
+    shared_data: &'a ThreadPoolSharedData,
+    id: u64,
+}
+
+impl<'a> Drop for WatchGuard<'a> {
+    fn drop(&mut self) {
+        let monitor = &self.shared_data.job_monitor;
+        let job = monitor.state.lock().unwrap().running.remove(&self.id);
+        if job.is_some_and(|job| job.compensated) {
+            self.shared_data.compensating_count.fetch_sub(1, Ordering::SeqCst);
+        }
+    }
+}
+
+impl JobMonitor {
+    pub fn new() -> JobMonitor {
+        JobMonitor {
+            enabled: AtomicBool::new(false),
+            state: Mutex::new(MonitorState {
+                watchdog: None,
+                running: HashMap::new(),
+                next_id: 0,
+                closed: false,
+            }),
+            changed: Condvar::new(),
+        }
+    }
+
+    /// Registers `job` as running on the current thread if it has a time
+    /// budget.
+    pub fn watch<'a>(shared_data: &'a ThreadPoolSharedData, job: &Job) -> Option<WatchGuard<'a>> {
+        let monitor = &shared_data.job_monitor;
+        if !monitor.enabled.load(Ordering::Acquire) {
+            return None;
+        }
+        let mut state = monitor.state.lock().unwrap();
+        let time_budget = job.time_budget()
+            .or_else(|| state.watchdog.as_ref().and_then(|watchdog| watchdog.default_time_budget))?;
+        let id = state.next_id;
+        state.next_id += 1;
+        state.running.insert(id, RunningJob {
+            job_name: job.name().map(|name| name.to_owned()),
+            thread_name: thread::current().name().map(|name| name.to_owned()),
+            started: Instant::now(),
+            time_budget,
+            overdue: false,
+            compensated: false,
+        });
+        monitor.changed.notify_one();
+        Some(WatchGuard { shared_data, id })
+    }
+
+    /// Stops the watchdog thread.
+    pub fn close(&self) {
+        self.state.lock().unwrap().closed = true;
+        self.changed.notify_all();
+    }
+}
+
+fn install(shared_data: &Arc<ThreadPoolSharedData>, watchdog: Watchdog) -> Result<(), PoolError> {
+    let monitor = &shared_data.job_monitor;
+    let mut state = monitor.state.lock().unwrap();
+    if state.closed {
+        return Err(PoolError::ShutDown);
+    }
+    if state.watchdog.is_none() {
+        spawn_watchdog(shared_data).map_err(PoolError::SpawnFailed)?;
+    }
+    state.watchdog = Some(watchdog);
+    monitor.enabled.store(true, Ordering::Release);
+    monitor.changed.notify_one();
+    Ok(())
+}
+
+fn spawn_watchdog(shared_data: &Arc<ThreadPoolSharedData>) -> io::Result<()> {
+    let mut builder = Builder::new();
+    if let Some(ref name) = shared_data.name {
+        builder = builder.name(index::helper_thread_name(name, "watchdog"));
+    }
+    let shared_data = shared_data.clone();
+    builder.spawn(move || run_watchdog(shared_data)).map(|_| ())
+}
+
+fn run_watchdog(shared_data: Arc<ThreadPoolSharedData>) {
+    let monitor = &shared_data.job_monitor;
+    loop {
+        let (watchdog, overdue) = {
+            let mut state = monitor.state.lock().unwrap();
+            loop {
+                if state.closed {
+                    return;
+                }
+                let now = Instant::now();
+                let compensate = state.watchdog.as_ref().is_some_and(|watchdog| watchdog.compensate);
+                let mut overdue = Vec::new();
+                for job in state.running.values_mut().filter(|job| !job.overdue) {
+                    if job.deadline() <= now {
+                        job.overdue = true;
+                        job.compensated = compensate;
+                        overdue.push(OverdueJob {
+                            job_name: job.job_name.clone(),
+                            thread_name: job.thread_name.clone(),
+                            elapsed: now - job.started,
+                            time_budget: job.time_budget,
+                        });
+                    }
+                }
+                if !overdue.is_empty() {
+                    if compensate {
+                        shared_data.compensating_count.fetch_add(overdue.len(), Ordering::SeqCst);
+                    }
+                    break (state.watchdog.clone().unwrap(), overdue);
+                }
+
+                let next_deadline = state.running
+                    .values()
+                    .filter(|job| !job.overdue)
+                    .map(|job| job.deadline())
+                    .min();
+                state = match next_deadline {
+                    Some(deadline) => monitor.changed.wait_timeout(state, deadline - now).unwrap().0,
+                    None => monitor.changed.wait(state).unwrap(),
+                };
+            }
+        };
+
+        for job in &overdue {
+            if watchdog.compensate {
+                shared_data.spawned_count.fetch_add(1, Ordering::SeqCst);
+                if spawn_in_pool(shared_data.clone()).is_err() {
+                    shared_data.spawned_count.fetch_sub(1, Ordering::SeqCst);
+                }
+            }
+            let _ = panic::catch_unwind(AssertUnwindSafe(|| (watchdog.callback)(job)));
+        }
+    }
+}
+
+impl ThreadPool {
+    /// Installs a watchdog that reports jobs running longer than their time
+    /// budget, replacing any previously installed watchdog.
+    ///
+    /// The watchdog runs on its own thread, which is started the first time a
+    /// watchdog is installed and exits when the pool is shut down.
+    pub fn set_watchdog(&mut self, watchdog: Watchdog) -> Result<(), PoolError> {
+        install(&self.shared_data, watchdog)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::{testing, JobBuilder, ThreadPool};
+    use super::Watchdog;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::mpsc::channel;
+    use std::sync::{Arc, Mutex};
+    use std::thread::sleep;
+    use std::time::Duration;
+
+    #[test]
+    fn test_reports_overdue_job() {
+        let (tx, rx) = channel();
+        let tx = Mutex::new(tx);
+        let mut pool = ThreadPool::new_with_name("watched".into(), 2);
+        pool.set_watchdog(Watchdog::new(move |job| tx.lock().unwrap().send(job.clone()).unwrap()))
+            .unwrap();
+
+        // Jobs that finish in time or have no budget are not reported.
+        JobBuilder::new().time_budget(Duration::from_secs(60)).execute(&pool, || {});
+        pool.execute(|| sleep(Duration::from_millis(100)));
+        JobBuilder::new()
+            .name("stuck")
+            .time_budget(Duration::from_millis(20))
+            .execute(&pool, || sleep(Duration::from_millis(200)));
+
+        let job = rx.recv().unwrap();
+        assert_eq!(job.job_name(), Some("stuck"));
+        assert_eq!(job.thread_name(), Some("watched"));
+        assert!(job.elapsed() >= Duration::from_millis(20));
+        assert_eq!(job.time_budget(), Duration::from_millis(20));
+
+        pool.join();
+        assert!(rx.try_recv().is_err());
+    }
+
+    #[test]
+    fn test_default_time_budget() {
+        let reported = Arc::new(AtomicUsize::new(0));
+        let mut pool = ThreadPool::new(2);
+        {
+            let reported = reported.clone();
+            pool.set_watchdog(Watchdog::new(move |_| {
+                        reported.fetch_add(1, Ordering::SeqCst);
+                    })
+                    .default_time_budget(Duration::from_millis(20)))
+                .unwrap();
+        }
+
+        pool.execute(|| sleep(Duration::from_millis(150)));
+        pool.execute(|| {});
+        pool.join();
+        assert_eq!(reported.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn test_compensate_overdue_job() {
+        let mut pool = ThreadPool::new(1);
+        pool.set_watchdog(Watchdog::new(|_| {})
+                .default_time_budget(Duration::from_millis(20))
+                .compensate(true))
+            .unwrap();
+
+        let release = testing::block(&pool);
+
+        // The only worker is stuck, but a compensating worker runs this job.
+        let (tx, rx) = channel();
+        pool.execute(move || tx.send(()).unwrap());
+        rx.recv_timeout(Duration::from_secs(10)).unwrap();
+        assert_eq!(pool.spawned_count(), 2);
+
+        release.send(()).unwrap();
+        pool.join();
+        for _ in 0..100 {
+            if pool.spawned_count() == 1 {
+                break;
+            }
+            sleep(Duration::from_millis(10));
+        }
+        assert_eq!(pool.spawned_count(), 1);
+    }
+}